@@ -0,0 +1,235 @@
+pub mod utils;
+
+use utils::*;
+
+const P2WPKH_ADDR: &str = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+const P2TR_ADDR: &str = "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0";
+const EXPIRY: i64 = 1_893_456_000;
+
+fn contract_id() -> ContractId {
+    ContractId::from([0x77; 32])
+}
+
+#[derive(Debug, Copy, Clone)]
+enum BeneficiaryKind {
+    Blinded,
+    WitnessP2wpkh,
+    WitnessP2tr,
+}
+
+fn beneficiary(kind: BeneficiaryKind, close_method: CloseMethod) -> Beneficiary {
+    match kind {
+        BeneficiaryKind::Blinded => {
+            let outpoint = Outpoint::from_str(FAKE_TXID).unwrap();
+            let seal = XChain::Bitcoin(GraphSeal::new_random(
+                close_method,
+                outpoint.txid,
+                outpoint.vout,
+            ));
+            Beneficiary::BlindedSeal(*seal.to_secret_seal().as_reduced_unsafe())
+        }
+        BeneficiaryKind::WitnessP2wpkh | BeneficiaryKind::WitnessP2tr => {
+            let addr = match kind {
+                BeneficiaryKind::WitnessP2wpkh => P2WPKH_ADDR,
+                _ => P2TR_ADDR,
+            };
+            Beneficiary::WitnessVout(Pay2Vout {
+                address: Address::from_str(addr).unwrap().payload,
+                method: close_method,
+            })
+        }
+    }
+}
+
+fn invoice_builder(
+    beneficiary: Beneficiary,
+    network: Network,
+    asset_schema: AssetSchema,
+    amount: u64,
+) -> RgbInvoiceBuilder {
+    let builder = RgbInvoiceBuilder::new(XChainNet::bitcoin(network, beneficiary))
+        .set_contract(contract_id())
+        .set_interface(asset_schema.iface_type_name());
+    match asset_schema {
+        AssetSchema::Uda => builder.set_allocation(UDA_FIXED_INDEX, amount).unwrap(),
+        AssetSchema::Nia | AssetSchema::Cfa => builder.set_amount_raw(amount),
+    }
+}
+
+fn check_round_trip(invoice: RgbInvoice) {
+    let invoice_str = invoice.to_string();
+    println!("invoice {invoice_str}");
+    let parsed = RgbInvoice::from_str(&invoice_str).unwrap();
+    assert_eq!(parsed, invoice);
+    assert_eq!(parsed.to_string(), invoice_str);
+}
+
+#[rstest]
+#[case(BeneficiaryKind::Blinded, CloseMethod::OpretFirst)]
+#[case(BeneficiaryKind::Blinded, CloseMethod::TapretFirst)]
+#[case(BeneficiaryKind::WitnessP2wpkh, CloseMethod::OpretFirst)]
+#[case(BeneficiaryKind::WitnessP2wpkh, CloseMethod::TapretFirst)]
+#[case(BeneficiaryKind::WitnessP2tr, CloseMethod::OpretFirst)]
+#[case(BeneficiaryKind::WitnessP2tr, CloseMethod::TapretFirst)]
+fn invoice_round_trip(
+    #[case] beneficiary_kind: BeneficiaryKind,
+    #[case] close_method: CloseMethod,
+) {
+    println!("beneficiary_kind {beneficiary_kind:?} close_method {close_method:?}");
+
+    let networks = [
+        Network::Mainnet,
+        Network::Testnet3,
+        Network::Signet,
+        Network::Regtest,
+    ];
+    for network in networks {
+        for asset_schema in AssetSchema::iter() {
+            let amounts = match asset_schema {
+                AssetSchema::Uda => vec![1],
+                AssetSchema::Nia | AssetSchema::Cfa => vec![0, 1, 600, u64::MAX],
+            };
+            for amount in amounts {
+                let beneficiary = beneficiary(beneficiary_kind, close_method);
+                let builder = invoice_builder(beneficiary, network, asset_schema, amount);
+                check_round_trip(builder.clone().finish());
+                check_round_trip(builder.set_expiry_timestamp(EXPIRY).finish());
+            }
+        }
+    }
+}
+
+#[test]
+fn invoice_round_trip_optional_fields() {
+    let beneficiary = beneficiary(BeneficiaryKind::WitnessP2wpkh, CloseMethod::OpretFirst);
+
+    // no contract nor interface
+    check_round_trip(
+        RgbInvoiceBuilder::new(XChainNet::bitcoin(Network::Regtest, beneficiary))
+            .set_amount_raw(100u64)
+            .finish(),
+    );
+
+    // no amount
+    check_round_trip(
+        RgbInvoiceBuilder::new(XChainNet::bitcoin(Network::Regtest, beneficiary))
+            .set_contract(contract_id())
+            .set_interface(AssetSchema::Nia.iface_type_name())
+            .finish(),
+    );
+    // query parameters unknown to the parser must be preserved
+    let mut invoice = invoice_builder(beneficiary, Network::Regtest, AssetSchema::Nia, 100)
+        .set_expiry_timestamp(EXPIRY)
+        .finish();
+    invoice
+        .unknown_query
+        .insert(s!("note"), s!("from-another-wallet"));
+    check_round_trip(invoice);
+}
+
+#[test]
+#[ignore = "fix needed"] // operation and assignment are written but not parsed back
+fn invoice_round_trip_operation_assignment() {
+    let beneficiary = beneficiary(BeneficiaryKind::WitnessP2wpkh, CloseMethod::OpretFirst);
+    check_round_trip(
+        invoice_builder(beneficiary, Network::Regtest, AssetSchema::Nia, 100)
+            .set_operation(fname!("transfer"))
+            .set_assignment(fname!("assetOwner"))
+            .finish(),
+    );
+}
+
+#[rstest]
+#[case(DescriptorType::Wpkh)]
+#[case(DescriptorType::Tr)]
+fn invoice_round_trip_from_wallet(#[case] wlt_desc: DescriptorType) {
+    println!("wlt_desc {wlt_desc:?}");
+
+    initialize();
+
+    let mut wlt = get_wallet(&wlt_desc);
+    let (contract_id, iface_type_name) = wlt.issue_nia(600, wlt.close_method(), None);
+    for invoice_type in [InvoiceType::Blinded(None), InvoiceType::Witness] {
+        let invoice = wlt.invoice(
+            contract_id,
+            &iface_type_name,
+            100,
+            wlt.close_method(),
+            invoice_type,
+        );
+        check_round_trip(invoice);
+    }
+}
+
+fn valid_invoice_str() -> String {
+    let beneficiary = beneficiary(BeneficiaryKind::WitnessP2wpkh, CloseMethod::OpretFirst);
+    invoice_builder(beneficiary, Network::Regtest, AssetSchema::Nia, 100)
+        .set_expiry_timestamp(EXPIRY)
+        .finish()
+        .to_string()
+}
+
+#[rstest]
+// other URI schemes, as pasted from BTC wallets
+#[case(
+    |s: String| s.replacen("rgb:", "bitcoin:", 1),
+    |e: &InvoiceParseError| matches!(e, InvoiceParseError::InvalidScheme(_))
+)]
+#[case(
+    |s: String| s.replacen("rgb:", "rgb20:", 1),
+    |e: &InvoiceParseError| matches!(e, InvoiceParseError::InvalidScheme(_))
+)]
+// URI authority is not allowed
+#[case(
+    |s: String| s.replacen("rgb:", "rgb://", 1),
+    |e: &InvoiceParseError| matches!(e, InvoiceParseError::Authority)
+)]
+// malformed contract ID
+#[case(
+    |s: String| {
+        let (id, rest) = s.trim_start_matches("rgb:").split_once('/').unwrap();
+        format!("rgb:{}/{rest}", &id[..id.len() - 1])
+    },
+    |e: &InvoiceParseError| matches!(e, InvoiceParseError::InvalidContractId(_))
+)]
+// malformed beneficiary
+#[case(
+    |s: String| s.split_once('+').unwrap().0.to_string() + "+bcrt:wvout:invalid",
+    |e: &InvoiceParseError| matches!(e, InvoiceParseError::Id(_))
+)]
+#[case(
+    |s: String| s.split_once('+').unwrap().0.to_string() + "+",
+    |e: &InvoiceParseError| matches!(e, InvoiceParseError::Beneficiary(_))
+)]
+// malformed query parameters
+#[case(
+    |s: String| s.replace("expiry=1893456000", "expiry=tomorrow"),
+    |e: &InvoiceParseError| matches!(e, InvoiceParseError::InvalidExpiration(_))
+)]
+#[case(
+    |s: String| s.replace("expiry=1893456000", "expiry"),
+    |e: &InvoiceParseError| matches!(e, InvoiceParseError::InvalidQueryParam(_))
+)]
+// not an invoice at all
+#[case(
+    |_: String| s!(""),
+    |e: &InvoiceParseError| matches!(e, InvoiceParseError::Invalid)
+)]
+#[case(
+    |_: String| s!("rgb:"),
+    |e: &InvoiceParseError| matches!(e, InvoiceParseError::ContractMissed)
+)]
+#[case(
+    |_: String| FAKE_TXID.to_string(),
+    |e: &InvoiceParseError| matches!(e, InvoiceParseError::InvalidScheme(_))
+)]
+fn invoice_parse_errors(
+    #[case] corrupt: fn(String) -> String,
+    #[case] is_expected_err: fn(&InvoiceParseError) -> bool,
+) {
+    let invoice_str = corrupt(valid_invoice_str());
+    println!("invoice {invoice_str}");
+    let err = RgbInvoice::from_str(&invoice_str).unwrap_err();
+    println!("err {err:?}");
+    assert!(is_expected_err(&err), "unexpected error {err:?}");
+}
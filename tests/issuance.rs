@@ -3,7 +3,7 @@ pub mod utils;
 use rstest_reuse::{self, *};
 use utils::*;
 
-const MEDIA_FPATH: &str = "tests/fixtures/rgb_logo.jpeg";
+const KITS_DIR: &str = "tests/fixtures/kits";
 
 #[template]
 #[rstest]
@@ -266,3 +266,286 @@ fn issue_cfa_multiple_utxos(wallet_desc: DescriptorType, close_method: CloseMeth
                 })))
     }
 }
+
+#[rstest]
+#[case(AssetSchema::Nia, false)]
+#[case(AssetSchema::Nia, true)]
+#[case(AssetSchema::Uda, false)]
+#[case(AssetSchema::Uda, true)]
+#[case(AssetSchema::Cfa, false)]
+#[case(AssetSchema::Cfa, true)]
+fn issue_from_kit_file(#[case] asset_schema: AssetSchema, #[case] armored: bool) {
+    println!("asset_schema {asset_schema:?} armored {armored}");
+
+    initialize();
+
+    let mut wallet = get_wallet(&DescriptorType::Wpkh);
+
+    let ext = if armored { "rgba" } else { "rgb" };
+    let kit_path = PathBuf::from(TEST_DATA_DIR)
+        .join(INTEGRATION_DATA_DIR)
+        .join("kits")
+        .join(format!("{asset_schema}.{ext}"));
+    asset_schema.export_kit(&kit_path, armored);
+    let kit = load_kit(&kit_path);
+    let spec = IssuanceSpec::load(&Path::new(KITS_DIR).join(format!("{asset_schema}.yaml")));
+    let (contract_id, iface_type_name) =
+        wallet.issue_with_spec(&kit, &spec, wallet.close_method(), vec![]);
+    assert_eq!(iface_type_name, asset_schema.iface_type_name());
+
+    match asset_schema {
+        AssetSchema::Nia => {
+            let contract = wallet.contract_iface_class::<Rgb20>(contract_id);
+            assert_eq!(contract.spec().ticker.to_string(), "NIATCKR");
+            assert_eq!(contract.spec().precision.decimals(), 2);
+            assert_eq!(
+                contract.spec().details.map(|d| d.to_string()),
+                Some(s!("issued from a kit file"))
+            );
+            assert_eq!(contract.contract_terms().text.to_string(), "NIA terms");
+            assert_eq!(contract.total_issued_supply().value(), 600);
+            wallet.check_allocations(
+                contract_id,
+                &iface_type_name,
+                asset_schema,
+                vec![400, 200],
+                false,
+            );
+        }
+        AssetSchema::Uda => {
+            let contract = wallet.contract_iface_class::<Rgb21>(contract_id);
+            assert_eq!(contract.spec().ticker.to_string(), "UDATCKR");
+            let token_data = contract.token_data();
+            assert_eq!(token_data.ticker.unwrap().to_string(), "TDTCKR");
+            assert_eq!(token_data.name.unwrap().to_string(), "token data name");
+            assert!(token_data.media.is_none());
+            wallet.check_uda_allocation(contract_id, UDA_FIXED_INDEX);
+        }
+        AssetSchema::Cfa => {
+            let contract = wallet.contract_iface_class::<Rgb25>(contract_id);
+            assert_eq!(contract.name().to_string(), "CFA asset name");
+            assert_eq!(
+                contract.details().map(|d| d.to_string()),
+                Some(s!("issued from a kit file"))
+            );
+            assert_eq!(contract.total_issued_supply().value(), 600);
+            wallet.check_allocations(
+                contract_id,
+                &iface_type_name,
+                asset_schema,
+                vec![600],
+                false,
+            );
+        }
+    }
+}
+
+/// Kits of in-house schemas can be dropped in the kits fixtures directory, each one with the
+/// issuance spec having the same file stem
+#[test]
+fn issue_from_fixture_kits() {
+    initialize();
+
+    let mut wallet = get_wallet(&DescriptorType::Wpkh);
+
+    let kit_files = kit_files(Path::new(KITS_DIR));
+    assert!(!kit_files.is_empty(), "no kits in {KITS_DIR}");
+    for (kit_path, spec_path) in kit_files {
+        println!("kit {kit_path:?} spec {spec_path:?}");
+        let kit = load_kit(&kit_path);
+        let spec = IssuanceSpec::load(&spec_path);
+        let (contract_id, iface_type_name) =
+            wallet.issue_with_spec(&kit, &spec, wallet.close_method(), vec![]);
+        assert_eq!(iface_type_name, spec.iface);
+        assert!(!wallet.history(contract_id, &iface_type_name).is_empty());
+    }
+}
+
+#[test]
+fn issue_with_spec_unknown_iface() {
+    initialize();
+
+    let mut wallet = get_wallet(&DescriptorType::Wpkh);
+
+    let mut spec = IssuanceSpec::load(&Path::new(KITS_DIR).join("nia.yaml"));
+    spec.iface = tn!("RGB20Unknown");
+    let res = wallet.try_issue_with_spec(
+        &AssetSchema::Nia.kit(),
+        &spec,
+        wallet.close_method(),
+        vec![],
+    );
+    assert!(
+        matches!(res, Err(TestError::UnknownIface(_))),
+        "unexpected {res:?}"
+    );
+}
+
+#[rstest]
+#[case(AssetSchema::Nia, false)]
+#[case(AssetSchema::Nia, true)]
+#[case(AssetSchema::Uda, false)]
+#[case(AssetSchema::Uda, true)]
+#[case(AssetSchema::Cfa, false)]
+#[case(AssetSchema::Cfa, true)]
+fn contract_export_import(#[case] asset_schema: AssetSchema, #[case] armored: bool) {
+    println!("asset_schema {asset_schema:?} armored {armored}");
+
+    initialize();
+
+    let mut wlt_1 = get_wallet(&DescriptorType::Wpkh);
+    let mut wlt_2 = get_wallet(&DescriptorType::Wpkh);
+    let mut wlt_3 = get_wallet(&DescriptorType::Tr);
+
+    let (contract_id, iface_type_name) = match asset_schema {
+        AssetSchema::Nia => wlt_1.issue_nia(600, wlt_1.close_method(), None),
+        AssetSchema::Uda => wlt_1.issue_uda(wlt_1.close_method(), None),
+        AssetSchema::Cfa => wlt_1.issue_cfa(600, wlt_1.close_method(), None),
+    };
+    // add some history to the contract
+    let amount = if asset_schema == AssetSchema::Uda {
+        1
+    } else {
+        200
+    };
+    wlt_1.send(
+        &mut wlt_2,
+        TransferType::Witness,
+        contract_id,
+        &iface_type_name,
+        amount,
+        1_000,
+        None,
+    );
+
+    let contract = wlt_1.export_contract(contract_id);
+    let ext = if armored { "rgba" } else { "rgb" };
+    let contract_path = wlt_1
+        .wallet_dir()
+        .join("contracts")
+        .join(format!("{contract_id}.{ext}"));
+    save_file_content(&contract, &contract_path, armored);
+    let loaded = load_contract(&contract_path);
+    assert_eq!(loaded.contract_id(), contract_id);
+    assert_eq!(loaded.consignment_id(), contract.consignment_id());
+
+    wlt_3.import_contract(loaded);
+    assert_eq!(
+        wlt_3.contract_info(contract_id),
+        wlt_1.contract_info(contract_id)
+    );
+    assert_eq!(
+        wlt_3.export_contract(contract_id).contract_id(),
+        contract_id
+    );
+    match asset_schema {
+        AssetSchema::Nia => {
+            let exported = wlt_1.contract_iface_class::<Rgb20>(contract_id);
+            let imported = wlt_3.contract_iface_class::<Rgb20>(contract_id);
+            assert_eq!(imported.spec(), exported.spec());
+            assert_eq!(imported.contract_terms(), exported.contract_terms());
+            assert_eq!(
+                imported.total_issued_supply(),
+                exported.total_issued_supply()
+            );
+        }
+        AssetSchema::Uda => {
+            let exported = wlt_1.contract_iface_class::<Rgb21>(contract_id);
+            let imported = wlt_3.contract_iface_class::<Rgb21>(contract_id);
+            assert_eq!(imported.spec(), exported.spec());
+            assert_eq!(imported.contract_terms(), exported.contract_terms());
+            assert_eq!(imported.token_data(), exported.token_data());
+        }
+        AssetSchema::Cfa => {
+            let exported = wlt_1.contract_iface_class::<Rgb25>(contract_id);
+            let imported = wlt_3.contract_iface_class::<Rgb25>(contract_id);
+            assert_eq!(imported.name(), exported.name());
+            assert_eq!(imported.precision(), exported.precision());
+            assert_eq!(imported.contract_terms(), exported.contract_terms());
+            assert_eq!(
+                imported.total_issued_supply(),
+                exported.total_issued_supply()
+            );
+        }
+    }
+    // the importer has no allocations of the contract
+    assert!(wlt_3
+        .contract_fungible_allocations(contract_id, &iface_type_name, false)
+        .is_empty());
+    assert!(wlt_3
+        .contract_data_allocations(contract_id, &iface_type_name)
+        .is_empty());
+}
+
+#[rstest]
+#[case(false)]
+#[case(true)]
+fn kit_round_trip(#[case] armored: bool) {
+    println!("armored {armored}");
+
+    let ext = if armored { "rgba" } else { "rgb" };
+    for asset_schema in AssetSchema::iter() {
+        let kit_path = PathBuf::from(TEST_DATA_DIR)
+            .join(INTEGRATION_DATA_DIR)
+            .join("kits")
+            .join(format!("{asset_schema}_round_trip.{ext}"));
+        asset_schema.export_kit(&kit_path, armored);
+        let kit = load_kit(&kit_path);
+        assert_eq!(kit, asset_schema.kit());
+        assert!(kit.validate().is_ok());
+    }
+}
+
+#[rstest]
+// binary kits
+#[case(false, |b: Vec<u8>| b[..b.len() / 2].to_vec())]
+#[case(false, |b: Vec<u8>| b[..4].to_vec())]
+#[case(false, |mut b: Vec<u8>| { b[0] ^= 0xff; b })]
+#[case(false, |b: Vec<u8>| b[..b.len() - 1].to_vec())]
+// armored kits
+#[case(true, |b: Vec<u8>| b[..b.len() / 2].to_vec())]
+#[case(true, alter_armored_body)]
+#[case(true, |_: Vec<u8>| b"-----BEGIN RGB KIT-----\n\n-----END RGB KIT-----\n".to_vec())]
+fn kit_corrupted(#[case] armored: bool, #[case] corrupt: fn(Vec<u8>) -> Vec<u8>) {
+    println!("armored {armored}");
+
+    initialize();
+
+    let ext = if armored { "rgba" } else { "rgb" };
+    let kit_dir = PathBuf::from(TEST_DATA_DIR)
+        .join(INTEGRATION_DATA_DIR)
+        .join("kits");
+    let kit_path = kit_dir.join(format!("nia_valid.{ext}"));
+    AssetSchema::Nia.export_kit(&kit_path, armored);
+    // the kit loads before being corrupted
+    load_kit(&kit_path);
+
+    let kit_bytes = corrupt(std::fs::read(&kit_path).unwrap());
+    let corrupted_path = kit_dir.join(format!("nia_corrupted_{}.{ext}", rand::random::<u32>()));
+    std::fs::write(&corrupted_path, kit_bytes).unwrap();
+
+    let err = try_load_kit(&corrupted_path).unwrap_err();
+    println!("err {err}");
+    assert!(matches!(err, TestError::Load(_)), "unexpected {err:?}");
+}
+
+#[test]
+fn kit_invalid() {
+    initialize();
+
+    let mut wallet = get_wallet(&DescriptorType::Wpkh);
+
+    // the kit decodes but misses the types used by the schema and the interface
+    let mut kit = AssetSchema::Nia.kit();
+    kit.types = TypeSystem::default();
+    let kit_path = wallet.wallet_dir().join("kits").join("nia_no_types.rgb");
+    save_file_content(&kit, &kit_path, false);
+
+    let kit = load_kit(&kit_path);
+    let err = wallet.try_import_kit(kit).unwrap_err();
+    println!("err {err}");
+    assert!(
+        matches!(err, TestError::Validation(_)),
+        "unexpected {err:?}"
+    );
+}
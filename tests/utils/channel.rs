@@ -0,0 +1,490 @@
+use super::*;
+
+const COMMITMENT_NONCE: u64 = u64::MAX - 1;
+const HTLC_NONCE: u64 = u64::MAX;
+const CHANNEL_STATIC_BLINDING: u64 = 666;
+const FUNDING_KEY_INDEX: u16 = 1000;
+
+/// Descriptor of the channel funding output, a P2WSH 2-of-2 multisig of a local and a remote key
+#[derive(Clone, Debug)]
+pub struct FundingDescr {
+    local: XpubDerivable,
+    remote: XpubDerivable,
+}
+
+impl FundingDescr {
+    pub fn new(local: XpubDerivable, remote: XpubDerivable) -> Self {
+        Self { local, remote }
+    }
+
+    fn witness_script(pks: [LegacyPk; 2]) -> WitnessScript {
+        let mut script = vec![OP_PUSHNUM_2];
+        for pk in pks {
+            let pk = pk.to_vec();
+            script.push(pk.len() as u8);
+            script.extend(pk);
+        }
+        script.extend([OP_PUSHNUM_2, OP_CHECKMULTISIG]);
+        WitnessScript::from_unsafe(script)
+    }
+}
+
+impl Display for FundingDescr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "wsh(multi(2,{},{}))", self.local, self.remote)
+    }
+}
+
+impl Derive<DerivedScript> for FundingDescr {
+    fn default_keychain(&self) -> Keychain {
+        Keychain::OUTER
+    }
+
+    fn keychains(&self) -> BTreeSet<Keychain> {
+        BTreeSet::from([Keychain::OUTER])
+    }
+
+    fn derive(
+        &self,
+        keychain: impl Into<Keychain>,
+        index: impl Into<NormalIndex>,
+    ) -> DerivedScript {
+        let terminal = Terminal::new(keychain.into(), index.into());
+        let pks = self.legacy_keyset(terminal).into_keys().collect::<Vec<_>>();
+        DerivedScript::Segwit(Self::witness_script([pks[0], pks[1]]))
+    }
+}
+
+impl Descriptor<XpubDerivable> for FundingDescr {
+    fn class(&self) -> SpkClass {
+        SpkClass::P2wsh
+    }
+
+    fn keys<'a>(&'a self) -> impl Iterator<Item = &'a XpubDerivable>
+    where
+        XpubDerivable: 'a,
+    {
+        [&self.local, &self.remote].into_iter()
+    }
+
+    fn vars<'a>(&'a self) -> impl Iterator<Item = &'a ()>
+    where
+        (): 'a,
+    {
+        std::iter::empty()
+    }
+
+    fn xpubs(&self) -> impl Iterator<Item = &XpubAccount> {
+        [self.local.spec(), self.remote.spec()].into_iter()
+    }
+
+    fn legacy_keyset(&self, terminal: Terminal) -> IndexMap<LegacyPk, KeyOrigin> {
+        self.keys()
+            .map(|key| {
+                let pk: CompressedPk = key.derive(terminal.keychain, terminal.index);
+                let origin = KeyOrigin::with(key.origin().clone(), terminal);
+                (LegacyPk::from(pk), origin)
+            })
+            .collect()
+    }
+
+    fn xonly_keyset(&self, _terminal: Terminal) -> IndexMap<XOnlyPk, TapDerivation> {
+        IndexMap::new()
+    }
+
+    fn legacy_witness(
+        &self,
+        keysigs: HashMap<&KeyOrigin, LegacyKeySig>,
+    ) -> Option<(SigScript, Witness)> {
+        let mut pks = vec![];
+        // an empty item is consumed by the CHECKMULTISIG off-by-one bug
+        let mut stack = vec![vec![]];
+        for key in self.keys() {
+            let keysig = keysigs
+                .iter()
+                .find(|(origin, _)| key.origin().is_subset_of(origin))
+                .map(|(_, ks)| ks)?;
+            pks.push(keysig.key);
+            stack.push(keysig.sig.to_vec());
+        }
+        let witness_script = Self::witness_script([pks[0], pks[1]]);
+        stack.push(witness_script.as_script_bytes().to_vec());
+        Some((SigScript::new(), Witness::from_consensus_stack(stack)))
+    }
+
+    fn taproot_witness(&self, _keysigs: HashMap<&KeyOrigin, TaprootKeySig>) -> Option<Witness> {
+        None
+    }
+}
+
+/// HTLC locked in a channel commitment
+#[derive(Clone, Copy, Debug)]
+pub struct Htlc {
+    /// Asset amount locked in the HTLC output
+    pub rgb_amt: u64,
+    /// BTC amount of the HTLC output
+    pub btc_amt: u64,
+}
+
+/// Colored TX spending a channel output, already consumed as fascia by the local wallet
+#[derive(Clone, Debug)]
+pub struct ChannelTx {
+    /// Unsigned PSBT of the TX
+    pub psbt: Psbt,
+    /// Beneficiaries of the RGB transition committed in the TX
+    pub asset_beneficiaries: AssetBeneficiariesMap,
+}
+
+impl ChannelTx {
+    pub fn txid(&self) -> Txid {
+        self.psbt.txid()
+    }
+}
+
+/// Channel state, represented by a commitment TX and the HTLC TXs spending its HTLC outputs
+#[derive(Clone, Debug)]
+pub struct ChannelState {
+    /// Asset amount owned by the local party
+    pub local_rgb_amt: u64,
+    /// Asset amount owned by the remote party
+    pub remote_rgb_amt: u64,
+    /// HTLCs pending in this state
+    pub htlcs: Vec<Htlc>,
+    /// Commitment TX
+    pub commitment: ChannelTx,
+    /// HTLC TXs, in the same order of `htlcs`
+    pub htlc_txs: Vec<ChannelTx>,
+    /// Whether a newer state has been committed
+    pub revoked: bool,
+}
+
+/// Simulator of the RGB state of a channel-like sequence of TXs between a local and a remote
+/// [`TestWallet`].
+///
+/// The funding output is a 2-of-2 multisig of both wallets and the RGB capacity is moved there
+/// when opening the channel. Successive colored TXs spending it are consumed as fascias by the
+/// local wallet, then one of them is signed by both parties and broadcast. There are no
+/// revocation keys nor penalty TXs and the HTLC outputs are plain outputs of the local wallet.
+///
+/// Commitment TXs send the remote balance to the first output, HTLCs to the following ones and
+/// the local balance to the change output.
+pub struct ChannelSim {
+    contract_id: ContractId,
+    iface_type_name: TypeName,
+    close_method: CloseMethod,
+    funding_descr: FundingDescr,
+    funding_terminal: Terminal,
+    funding_outpoint: Outpoint,
+    funding_btc_amt: u64,
+    rgb_capacity: u64,
+    remote_address: Address,
+    htlc_derived_addr: DerivedAddr,
+    pre_funding_height: u32,
+    states: Vec<ChannelState>,
+}
+
+impl ChannelSim {
+    /// Open the channel, moving the RGB capacity from the given local UTXO to a new funding
+    /// output of the given BTC amount
+    pub fn open(
+        local: &mut TestWallet,
+        remote: &TestWallet,
+        contract_id: ContractId,
+        iface_type_name: &TypeName,
+        asset_utxo: Outpoint,
+        rgb_capacity: u64,
+        funding_btc_amt: u64,
+    ) -> Self {
+        let funding_descr = FundingDescr::new(local.xpub_derivable(), remote.xpub_derivable());
+        let funding_terminal =
+            Terminal::new(Keychain::OUTER, NormalIndex::normal(FUNDING_KEY_INDEX));
+        let funding_address = funding_descr
+            .derive_address(
+                AddressNetwork::from(local.network()),
+                funding_terminal.keychain,
+                funding_terminal.index,
+            )
+            .unwrap();
+
+        let (mut psbt, _meta) = local.construct_psbt(
+            vec![asset_utxo],
+            vec![(funding_address, Some(funding_btc_amt))],
+            None,
+        );
+        let coloring_info = ColoringInfo {
+            asset_info_map: HashMap::from([(
+                contract_id,
+                AssetColoringInfo {
+                    iface: iface_type_name.clone(),
+                    input_outpoints: vec![asset_utxo],
+                    output_map: HashMap::from([(0, rgb_capacity)]),
+                    nonfungible_vout: None,
+                    static_blinding: None,
+                },
+            )]),
+            static_blinding: None,
+            nonce: None,
+            close_method: local.close_method(),
+        };
+        let (fascia, _asset_beneficiaries) = local.color_psbt(&mut psbt, coloring_info);
+        let tx = local.sign_finalize_extract(&mut psbt);
+        local.broadcast_tx(&tx);
+        local.mine_tx(&tx.txid(), false);
+        local.consume_fascia(fascia, tx.txid());
+        local.sync();
+        let funding_height = local
+            .get_tx_height(&tx.txid())
+            .expect("funding TX should be mined");
+
+        Self {
+            contract_id,
+            iface_type_name: iface_type_name.clone(),
+            close_method: local.close_method(),
+            funding_descr,
+            funding_terminal,
+            funding_outpoint: Outpoint::new(tx.txid(), 0),
+            funding_btc_amt,
+            rgb_capacity,
+            remote_address: remote.get_address(),
+            htlc_derived_addr: local.get_derived_address(),
+            pre_funding_height: funding_height - 1,
+            states: vec![],
+        }
+    }
+
+    pub fn contract_id(&self) -> ContractId {
+        self.contract_id
+    }
+
+    pub fn states(&self) -> &[ChannelState] {
+        &self.states
+    }
+
+    pub fn current_state(&self) -> &ChannelState {
+        self.states.last().expect("no state has been committed yet")
+    }
+
+    pub fn sync_and_update_witnesses(&self, wlt: &mut TestWallet) {
+        wlt.sync_and_update_witnesses(Some(self.pre_funding_height));
+    }
+
+    fn coloring_info(
+        &self,
+        input_outpoint: Outpoint,
+        output_map: HashMap<u32, u64>,
+        nonce: Option<u64>,
+    ) -> ColoringInfo {
+        ColoringInfo {
+            asset_info_map: HashMap::from([(
+                self.contract_id,
+                AssetColoringInfo {
+                    iface: self.iface_type_name.clone(),
+                    input_outpoints: vec![input_outpoint],
+                    output_map,
+                    nonfungible_vout: None,
+                    static_blinding: Some(CHANNEL_STATIC_BLINDING),
+                },
+            )]),
+            static_blinding: Some(CHANNEL_STATIC_BLINDING),
+            nonce,
+            close_method: self.close_method,
+        }
+    }
+
+    fn construct_funding_spend(
+        &self,
+        local: &mut TestWallet,
+        beneficiaries: Vec<(Address, Option<u64>)>,
+    ) -> (Psbt, PsbtMeta) {
+        local.construct_psbt_offchain_for(
+            &self.funding_descr,
+            vec![(
+                self.funding_outpoint,
+                self.funding_btc_amt,
+                self.funding_terminal,
+            )],
+            beneficiaries,
+            None,
+        )
+    }
+
+    /// Sign a TX spending the funding output with the keys of both parties
+    fn sign_funding_spend(
+        &self,
+        local: &TestWallet,
+        remote: &TestWallet,
+        channel_tx: &ChannelTx,
+    ) -> Tx {
+        let mut psbt = channel_tx.psbt.clone();
+        local.sign(&mut psbt);
+        remote.sign(&mut psbt);
+        psbt.finalize(&self.funding_descr);
+        psbt.extract().unwrap()
+    }
+
+    fn color_and_consume(
+        &self,
+        local: &mut TestWallet,
+        mut psbt: Psbt,
+        coloring_info: ColoringInfo,
+    ) -> ChannelTx {
+        let (fascia, asset_beneficiaries) = local.color_psbt(&mut psbt, coloring_info);
+        local.consume_fascia(fascia, psbt.txid());
+        ChannelTx {
+            psbt,
+            asset_beneficiaries,
+        }
+    }
+
+    /// Commit a new channel state, revoking the previous one
+    pub fn commit(
+        &mut self,
+        local: &mut TestWallet,
+        remote_rgb_amt: u64,
+        remote_btc_amt: u64,
+        htlcs: Vec<Htlc>,
+    ) -> usize {
+        let htlc_rgb_amt: u64 = htlcs.iter().map(|h| h.rgb_amt).sum();
+        let local_rgb_amt = self
+            .rgb_capacity
+            .checked_sub(remote_rgb_amt + htlc_rgb_amt)
+            .expect("channel capacity exceeded");
+
+        let mut beneficiaries = vec![(self.remote_address, Some(remote_btc_amt))];
+        beneficiaries.extend(
+            htlcs
+                .iter()
+                .map(|h| (self.htlc_derived_addr.addr, Some(h.btc_amt))),
+        );
+        let (psbt, _meta) = self.construct_funding_spend(local, beneficiaries);
+        let change_vout = htlcs.len() as u32 + 1;
+        let mut output_map = HashMap::from([(0, remote_rgb_amt), (change_vout, local_rgb_amt)]);
+        output_map.extend(
+            htlcs
+                .iter()
+                .enumerate()
+                .map(|(i, h)| (i as u32 + 1, h.rgb_amt)),
+        );
+        let coloring_info =
+            self.coloring_info(self.funding_outpoint, output_map, Some(COMMITMENT_NONCE));
+        let commitment = self.color_and_consume(local, psbt, coloring_info);
+
+        let mut htlc_txs = vec![];
+        for (i, htlc) in htlcs.iter().enumerate() {
+            let htlc_vout = i as u32 + 1;
+            let input_outpoint = Outpoint::new(commitment.txid(), htlc_vout);
+            let (psbt, _meta) = local.construct_psbt_offchain(
+                vec![(
+                    input_outpoint,
+                    htlc.btc_amt,
+                    self.htlc_derived_addr.terminal,
+                )],
+                vec![],
+                None,
+            );
+            let coloring_info = self.coloring_info(
+                input_outpoint,
+                HashMap::from([(0, htlc.rgb_amt)]),
+                Some(HTLC_NONCE),
+            );
+            htlc_txs.push(self.color_and_consume(local, psbt, coloring_info));
+        }
+
+        if let Some(state) = self.states.last_mut() {
+            state.revoked = true;
+        }
+        self.states.push(ChannelState {
+            local_rgb_amt,
+            remote_rgb_amt,
+            htlcs,
+            commitment,
+            htlc_txs,
+            revoked: false,
+        });
+        self.states.len() - 1
+    }
+
+    fn deliver_remote_consignment(
+        &self,
+        local: &TestWallet,
+        remote: &mut TestWallet,
+        channel_tx: &ChannelTx,
+    ) {
+        let remote_beneficiaries = beneficiaries_for_vout(&channel_tx.asset_beneficiaries, 0);
+        for consignment in local.create_consignments(remote_beneficiaries, channel_tx.txid()) {
+            remote.accept_transfer(consignment, None);
+        }
+    }
+
+    fn broadcast_state(
+        &self,
+        local: &mut TestWallet,
+        remote: &mut TestWallet,
+        state_index: usize,
+    ) -> Tx {
+        let state = &self.states[state_index];
+        let tx = self.sign_funding_spend(local, remote, &state.commitment);
+        local.broadcast_tx(&tx);
+        local.mine_tx(&tx.txid(), false);
+        for htlc_tx in &state.htlc_txs {
+            let tx = local.sign_finalize_extract(&mut htlc_tx.psbt.clone());
+            local.broadcast_tx(&tx);
+            local.mine_tx(&tx.txid(), false);
+        }
+        self.sync_and_update_witnesses(local);
+        self.deliver_remote_consignment(local, remote, &state.commitment);
+        self.sync_and_update_witnesses(remote);
+        tx
+    }
+
+    /// Close the channel with a TX spending the funding output that pays both parties their
+    /// balance, without HTLCs
+    pub fn cooperative_close(
+        &mut self,
+        local: &mut TestWallet,
+        remote: &mut TestWallet,
+        remote_btc_amt: u64,
+    ) -> Tx {
+        let state = self.current_state().clone();
+        if !state.htlcs.is_empty() {
+            panic!("cannot cooperatively close a channel with pending HTLCs");
+        }
+        let beneficiaries = vec![(self.remote_address, Some(remote_btc_amt))];
+        let (psbt, _meta) = self.construct_funding_spend(local, beneficiaries);
+        let output_map = HashMap::from([(0, state.remote_rgb_amt), (1, state.local_rgb_amt)]);
+        // no nonce: the closing TX must be ordered after all commitments
+        let coloring_info = self.coloring_info(self.funding_outpoint, output_map, None);
+        let closing = self.color_and_consume(local, psbt, coloring_info);
+
+        let tx = self.sign_funding_spend(local, remote, &closing);
+        local.broadcast_tx(&tx);
+        local.mine_tx(&tx.txid(), false);
+        self.sync_and_update_witnesses(local);
+        self.deliver_remote_consignment(local, remote, &closing);
+        self.sync_and_update_witnesses(remote);
+        if let Some(state) = self.states.last_mut() {
+            state.revoked = true;
+        }
+        tx
+    }
+
+    /// Unilaterally close the channel broadcasting the latest commitment and its HTLC TXs
+    pub fn force_close(&mut self, local: &mut TestWallet, remote: &mut TestWallet) -> Tx {
+        let state_index = self.states.len() - 1;
+        self.broadcast_state(local, remote, state_index)
+    }
+
+    /// Broadcast the commitment of a revoked state and its HTLC TXs.
+    ///
+    /// Since there is no penalty path, this only checks that RGB accepts an older state once it
+    /// gets mined, not that the remote party could punish the local one.
+    pub fn broadcast_revoked(
+        &mut self,
+        local: &mut TestWallet,
+        remote: &mut TestWallet,
+        state_index: usize,
+    ) -> Tx {
+        assert!(self.states[state_index].revoked, "state is not revoked");
+        self.broadcast_state(local, remote, state_index)
+    }
+}
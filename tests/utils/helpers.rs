@@ -6,6 +6,23 @@ pub struct TestWallet {
     signer: Option<TestnetSigner>,
     wallet_dir: PathBuf,
     instance: u8,
+    allow_burns: bool,
+    consignment_format: ConsignmentFormat,
+    seed: Option<Vec<u8>>,
+}
+
+/// External signer holding only the wallet private keys
+pub struct TestSigner {
+    signer: TestnetSigner,
+}
+
+impl TestSigner {
+    /// Sign a serialized PSBT, returning it serialized
+    pub fn sign(&self, psbt_bytes: &[u8]) -> Vec<u8> {
+        let mut psbt = Psbt::deserialize(psbt_bytes).unwrap();
+        let _sig_count = psbt.sign(&self.signer).unwrap();
+        psbt.serialize(psbt.version)
+    }
 }
 
 enum WalletAccount {
@@ -65,6 +82,10 @@ impl Filter<'_> {
     }
 }
 
+/// Wallet descriptor types, mapped to the `RgbDescr` variants.
+///
+/// Multisig descriptors (e.g. wsh-multi or taproot multi-key) are not available as RGB descriptors
+/// yet, hence assets on multisig UTXOs can't be tested until `RgbDescr` supports them.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum DescriptorType {
     Wpkh,
@@ -90,6 +111,339 @@ pub enum ReorgType {
     Revert,
 }
 
+#[derive(Debug, Copy, Clone)]
+pub enum BurnFlow {
+    BtcSend,
+    ConstructPsbt,
+    PsbtAddInput,
+    PartialColoring,
+}
+
+/// Policy used to select the UTXOs spent by a TX
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CoinSelection {
+    /// Largest UTXOs first, regardless of their RGB allocations
+    LargestFirst,
+    /// Plain BTC UTXOs first, falling back to colored ones
+    PreferPlain,
+    /// Plain BTC UTXOs only
+    PlainOnly,
+}
+
+/// Ways a sender can fail to pay an invoice while producing a valid consignment
+#[derive(Debug, Copy, Clone)]
+pub enum PaymentFault {
+    /// Paying a seal the receiver doesn't own
+    SealNotOwned,
+    /// Paying less than the invoiced amount
+    Underpaid,
+    /// Paying with another contract
+    WrongContract,
+    /// Delivering the consignment before its witness TX gets mined
+    UnconfirmedWitness,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum SwapCheat {
+    Amount,
+    Seal,
+}
+
+/// Ways an invoice can be unpayable by the payer
+#[derive(Debug, Copy, Clone)]
+pub enum InvoiceMismatch {
+    /// Expiry timestamp in the past
+    Expired,
+    /// Beneficiary on a network different from the payer one
+    WrongNetwork,
+    /// Contract unknown to the payer
+    WrongContract,
+    /// Interface not implemented by the contract
+    WrongIface,
+    /// Close method the beneficiary output can't host (tapret on a non-taproot address)
+    UnsupportedCloseMethod,
+}
+
+impl InvoiceMismatch {
+    /// Alter a valid invoice to introduce the mismatch
+    pub fn apply(&self, mut invoice: RgbInvoice) -> RgbInvoice {
+        match self {
+            Self::Expired => {
+                invoice.expiry = Some(OffsetDateTime::now_utc().unix_timestamp() - 3600);
+            }
+            Self::WrongNetwork => {
+                let beneficiary = invoice.beneficiary.into_inner();
+                invoice.beneficiary = XChainNet::bitcoin(Network::Mainnet, beneficiary);
+            }
+            Self::WrongContract => {
+                invoice.contract = Some(ContractId::from([0x77; 32]));
+            }
+            Self::WrongIface => {
+                invoice.iface = Some(AssetSchema::Uda.iface_type_name());
+            }
+            Self::UnsupportedCloseMethod => {
+                let Beneficiary::WitnessVout(pay2vout) = invoice.beneficiary.into_inner() else {
+                    panic!("close method can be changed on witness invoices only");
+                };
+                let beneficiary = Beneficiary::WitnessVout(Pay2Vout {
+                    address: pay2vout.address,
+                    method: CloseMethod::TapretFirst,
+                });
+                invoice.beneficiary = XChainNet::bitcoin(Network::Regtest, beneficiary);
+            }
+        }
+        invoice
+    }
+}
+
+/// Stock error of the test wallets, with the given operation-specific error
+pub type WalletStockError<E = Infallible> = StockError<MemStash, MemState, MemIndex, E>;
+
+/// Error returned by the fallible (`try_*`) [`TestWallet`] helpers
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum TestError {
+    /// The invoice can't be paid
+    Pay(PayError),
+    /// The consignment or the kit is not valid
+    Validation(Status),
+    /// The consignment, kit or contract can't be read or decoded
+    Load(LoadFailure),
+    /// The issuance spec doesn't describe a valid contract
+    Spec(SpecError),
+    /// The stock refused the operation
+    Stock(WalletStockError),
+    /// The stock can't compose the transition
+    Compose(WalletStockError<ComposeError>),
+    /// The PSBT can't be colored, signed or finalized
+    Psbt(PsbtError),
+    /// The witness can't be resolved
+    Resolver(WitnessResolverError),
+    /// The indexer failed
+    Indexer(AnyIndexerError),
+    /// The wallet can't sync with the indexer
+    Sync(Vec<AnyIndexerError>),
+    /// The interface is not part of the kit or has no implementation (with its schema) in it
+    UnknownIface(TypeName),
+    /// The contract or transition can't be built
+    Builder(BuilderError),
+    /// The coloring info doesn't match the PSBT or the available allocations
+    Coloring(ColoringError),
+    /// Signing would burn the allocations on the given outpoints
+    Burn(Vec<(Outpoint, ContractId)>),
+    /// The wallet has no signer
+    WatchOnly,
+    /// The consignment doesn't pay the invoice
+    Rejected(String),
+    /// The invoice beneficiary is on a network different from the wallet one
+    WrongNetwork(ChainNet),
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Pay(e) => write!(f, "payment error: {e}"),
+            Self::Validation(status) => write!(f, "invalid consignment: {status:?}"),
+            Self::Load(e) => write!(f, "load error: {e:?}"),
+            Self::Spec(e) => write!(f, "issuance spec error: {e:?}"),
+            Self::Stock(e) => write!(f, "stock error: {e}"),
+            Self::Compose(e) => write!(f, "compose error: {e}"),
+            Self::Psbt(e) => write!(f, "PSBT error: {e:?}"),
+            Self::Resolver(e) => write!(f, "resolver error: {e:?}"),
+            Self::Indexer(e) => write!(f, "indexer error: {e}"),
+            Self::Sync(errors) => write!(f, "sync error: {errors:?}"),
+            Self::UnknownIface(iface) => write!(f, "interface {iface} not in kit"),
+            Self::Builder(e) => write!(f, "builder error: {e}"),
+            Self::Coloring(e) => write!(f, "coloring error: {e:?}"),
+            Self::Burn(burned) => write!(f, "signing would burn assets: {burned:?}"),
+            Self::WatchOnly => write!(
+                f,
+                "watch-only wallet, PSBT must be signed by an external signer"
+            ),
+            Self::Rejected(reason) => write!(f, "consignment rejected: {reason}"),
+            Self::WrongNetwork(chain_net) => write!(f, "invoice for {chain_net:?}"),
+        }
+    }
+}
+
+/// Reason why a consignment, a kit or a contract can't be loaded
+#[derive(Debug)]
+pub enum LoadFailure {
+    /// The file can't be read
+    Io(std::io::Error),
+    /// The armored data is not UTF-8 text
+    Utf8(std::str::Utf8Error),
+    /// The YAML data doesn't describe the container
+    Yaml(serde_yaml::Error),
+    /// The binary data exceeds the container size limit
+    TooLarge(ConfinementError),
+    /// The binary data is not the strict encoding of the container
+    Binary(DeserializeError),
+    /// The binary file is not valid
+    File(LoadError),
+    /// The ASCII armor is not valid
+    Armor(StrictArmorError),
+    /// The armored consignment is not of the expected type (contract or transfer)
+    ConsignmentType,
+}
+
+impl From<ConsignmentParseError> for LoadFailure {
+    fn from(e: ConsignmentParseError) -> Self {
+        match e {
+            ConsignmentParseError::Armor(e) => Self::Armor(e),
+            ConsignmentParseError::Type => Self::ConsignmentType,
+        }
+    }
+}
+
+impl From<LoadFailure> for TestError {
+    fn from(e: LoadFailure) -> Self {
+        Self::Load(e)
+    }
+}
+
+/// Reason why an issuance spec can't be turned into a contract
+#[derive(Debug)]
+pub enum SpecError {
+    /// The owned state has no type and a value which is not an amount
+    InvalidAmount(FieldName, StrictVal),
+    /// The state has no type
+    MissingType(FieldName),
+    /// The type is not among the ones of the standard interfaces
+    UnknownType(TypeFqn),
+    /// The state value doesn't match its type
+    Typify(FieldName, TypifyError),
+    /// The state value can't be serialized
+    Serialize(FieldName, SerializeError),
+}
+
+impl From<SpecError> for TestError {
+    fn from(e: SpecError) -> Self {
+        Self::Spec(e)
+    }
+}
+
+impl From<PayError> for TestError {
+    fn from(e: PayError) -> Self {
+        Self::Pay(e)
+    }
+}
+
+impl From<Status> for TestError {
+    fn from(status: Status) -> Self {
+        Self::Validation(status)
+    }
+}
+
+impl From<WalletStockError> for TestError {
+    fn from(e: WalletStockError) -> Self {
+        Self::Stock(e)
+    }
+}
+
+impl From<WalletStockError<ComposeError>> for TestError {
+    fn from(e: WalletStockError<ComposeError>) -> Self {
+        Self::Compose(e)
+    }
+}
+
+impl From<WitnessResolverError> for TestError {
+    fn from(e: WitnessResolverError) -> Self {
+        Self::Resolver(e)
+    }
+}
+
+impl From<BuilderError> for TestError {
+    fn from(e: BuilderError) -> Self {
+        Self::Builder(e)
+    }
+}
+
+impl From<ColoringError> for TestError {
+    fn from(e: ColoringError) -> Self {
+        Self::Coloring(e)
+    }
+}
+
+impl From<PsbtError> for TestError {
+    fn from(e: PsbtError) -> Self {
+        Self::Psbt(e)
+    }
+}
+
+/// Error of the PSBT step that failed
+#[derive(Debug)]
+pub enum PsbtError {
+    OpretHost(OpretKeyError),
+    TapretHost(TapretKeyError),
+    MpcEntropy(MpcPsbtError),
+    RgbConsumer(KeyAlreadyPresent),
+    Rgb(RgbPsbtError),
+    Sign(SignError),
+    Extract(UnfinalizedInputs),
+}
+
+impl From<OpretKeyError> for PsbtError {
+    fn from(e: OpretKeyError) -> Self {
+        Self::OpretHost(e)
+    }
+}
+
+impl From<TapretKeyError> for PsbtError {
+    fn from(e: TapretKeyError) -> Self {
+        Self::TapretHost(e)
+    }
+}
+
+impl From<MpcPsbtError> for PsbtError {
+    fn from(e: MpcPsbtError) -> Self {
+        Self::MpcEntropy(e)
+    }
+}
+
+impl From<KeyAlreadyPresent> for PsbtError {
+    fn from(e: KeyAlreadyPresent) -> Self {
+        Self::RgbConsumer(e)
+    }
+}
+
+impl From<RgbPsbtError> for PsbtError {
+    fn from(e: RgbPsbtError) -> Self {
+        Self::Rgb(e)
+    }
+}
+
+impl From<SignError> for PsbtError {
+    fn from(e: SignError) -> Self {
+        Self::Sign(e)
+    }
+}
+
+impl From<UnfinalizedInputs> for PsbtError {
+    fn from(e: UnfinalizedInputs) -> Self {
+        Self::Extract(e)
+    }
+}
+
+/// Reason why the coloring info can't be applied
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColoringError {
+    /// The output map refers to a vout the PSBT doesn't have
+    InvalidVout(u32),
+    /// The interface doesn't define the assignment
+    UnknownAssignment(FieldName),
+    /// A blinded invoice has a witness beneficiary
+    NotBlinded,
+    /// A paid invoice has no amount
+    NoAmount,
+    /// The outputs get more than the inputs allocations
+    InsufficientAmount { available: u64, requested: u64 },
+    /// No output can host the commitment with the given close method
+    NoHost(CloseMethod),
+    /// The inputs have non-fungible states but no vout receives them
+    NoNonFungibleVout,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum TransferType {
     Blinded,
@@ -116,6 +470,224 @@ impl From<TransferType> for InvoiceType {
     }
 }
 
+/// Wire format used to deliver consignments to the receiver
+#[derive(Debug, EnumIter, Copy, Clone, Default, PartialEq)]
+pub enum ConsignmentFormat {
+    #[default]
+    Yaml,
+    /// Strict encoding
+    Binary,
+    /// ASCII armor, with checksum
+    Armored,
+}
+
+impl fmt::Display for ConsignmentFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+impl ConsignmentFormat {
+    pub fn serialize(&self, consignment: &Transfer) -> Vec<u8> {
+        match self {
+            Self::Yaml => serde_yaml::to_string(consignment).unwrap().into_bytes(),
+            Self::Binary => consignment.to_strict_serialized::<U32>().unwrap().release(),
+            Self::Armored => consignment.to_string().into_bytes(),
+        }
+    }
+
+    pub fn deserialize(&self, bytes: &[u8]) -> Result<Transfer, TestError> {
+        let consignment = match self {
+            Self::Yaml => serde_yaml::from_slice(bytes).map_err(LoadFailure::Yaml)?,
+            Self::Binary => {
+                let data = Confined::<Vec<u8>, 0, U32>::try_from(bytes.to_vec())
+                    .map_err(LoadFailure::TooLarge)?;
+                Transfer::from_strict_serialized::<U32>(data).map_err(LoadFailure::Binary)?
+            }
+            Self::Armored => {
+                let armored = std::str::from_utf8(bytes).map_err(LoadFailure::Utf8)?;
+                Transfer::from_str(armored).map_err(LoadFailure::from)?
+            }
+        };
+        Ok(consignment)
+    }
+}
+
+/// Status of a [`PendingTransfer`], as the wallet app shows it to users
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransferStatus {
+    /// The witness TX is signed but not broadcast yet
+    Created,
+    /// The witness TX is in the mempool
+    Broadcast,
+    /// The receiver got the consignment
+    ConsignmentDelivered,
+    /// The receiver validated and accepted the consignment
+    Accepted,
+    /// The witness TX is mined with the given number of confirmations
+    Confirmed(u32),
+    /// The transfer can't complete anymore
+    Failed(String),
+    /// The witness TX has been replaced (RBF) by the one with the given ID
+    Replaced(Txid),
+}
+
+/// Transfer tracked through its lifecycle, from creation to confirmation, as returned by
+/// [`TestWallet::transfer`].
+///
+/// Status changes are driven by the explicit steps (broadcast, delivery, acceptance, replacement)
+/// and by [`PendingTransfer::update`], which follows the witness TX on chain, also across reorgs.
+#[derive(Debug, Clone)]
+pub struct PendingTransfer {
+    invoice: RgbInvoice,
+    consignment: Transfer,
+    tx: Tx,
+    status: TransferStatus,
+    history: Vec<TransferStatus>,
+}
+
+impl PendingTransfer {
+    fn new(invoice: RgbInvoice, consignment: Transfer, tx: Tx, status: TransferStatus) -> Self {
+        Self {
+            invoice,
+            consignment,
+            tx,
+            status: status.clone(),
+            history: vec![status],
+        }
+    }
+
+    pub fn txid(&self) -> Txid {
+        self.tx.txid()
+    }
+
+    pub fn consignment(&self) -> &Transfer {
+        &self.consignment
+    }
+
+    pub fn tx(&self) -> &Tx {
+        &self.tx
+    }
+
+    /// Stop tracking the transfer, keeping its consignment and witness TX
+    pub fn into_parts(self) -> (Transfer, Tx) {
+        (self.consignment, self.tx)
+    }
+
+    pub fn status(&self) -> &TransferStatus {
+        &self.status
+    }
+
+    /// All the statuses the transfer went through, the current one included
+    pub fn history(&self) -> &[TransferStatus] {
+        &self.history
+    }
+
+    fn set_status(&mut self, status: TransferStatus) {
+        if status != self.status {
+            println!("transfer {} status {status:?}", self.txid());
+            self.status = status.clone();
+            self.history.push(status);
+        }
+    }
+
+    fn expect_status(&self, expected: &[TransferStatus], action: &str) {
+        assert!(
+            expected.contains(&self.status),
+            "can't {action} a transfer with status {:?}",
+            self.status
+        );
+    }
+
+    /// Broadcast the witness TX, failing the transfer if the indexer refuses it (e.g. because its
+    /// inputs have already been spent)
+    pub fn broadcast(&mut self, sender: &TestWallet) -> &TransferStatus {
+        self.expect_status(&[TransferStatus::Created], "broadcast");
+        match sender.try_broadcast_tx(&self.tx) {
+            Ok(()) => self.set_status(TransferStatus::Broadcast),
+            Err(e) => self.set_status(TransferStatus::Failed(e.to_string())),
+        }
+        &self.status
+    }
+
+    /// Deliver the consignment to the receiver, in the wire format of the sender
+    pub fn deliver(&mut self, sender: &TestWallet) -> &TransferStatus {
+        self.expect_status(&[TransferStatus::Broadcast], "deliver");
+        self.consignment = sender.deliver_consignment(&self.consignment);
+        self.set_status(TransferStatus::ConsignmentDelivered);
+        &self.status
+    }
+
+    pub fn accept(&mut self, receiver: &mut TestWallet) -> &TransferStatus {
+        self.expect_status(&[TransferStatus::ConsignmentDelivered], "accept");
+        match receiver.try_accept_transfer(self.consignment.clone(), None) {
+            Ok(()) => self.set_status(TransferStatus::Accepted),
+            Err(e) => self.set_status(TransferStatus::Failed(e.to_string())),
+        }
+        &self.status
+    }
+
+    /// Replace the witness TX paying a higher fee, returning the new transfer which needs to be
+    /// delivered again, since its consignment differs
+    pub fn replace(&mut self, sender: &mut TestWallet, fee: u64) -> PendingTransfer {
+        assert!(
+            sender.get_tx_height(&self.txid()).is_none(),
+            "can't replace a mined transfer"
+        );
+        let replacement = sender.transfer(self.invoice.clone(), None, Some(fee), true, None);
+        self.set_status(TransferStatus::Replaced(replacement.txid()));
+        replacement
+    }
+
+    /// Follow the witness TX on chain: mined TXs are confirmed, TXs reorged back to the mempool
+    /// return to their previous status and TXs no longer known are replaced by the TX spending
+    /// their inputs, or failed if there's none
+    pub fn update(&mut self, wallet: &TestWallet) -> &TransferStatus {
+        if matches!(
+            self.status,
+            TransferStatus::Created | TransferStatus::Failed(_) | TransferStatus::Replaced(_)
+        ) {
+            return &self.status;
+        }
+        let txid = self.txid();
+        match wallet.get_witness_ord(&txid) {
+            WitnessOrd::Mined(_) => {
+                let confirmations = wallet.get_tx_confirmations(&txid).unwrap();
+                self.set_status(TransferStatus::Confirmed(confirmations));
+            }
+            WitnessOrd::Archived => {
+                let replacing_txid = self
+                    .tx
+                    .inputs
+                    .iter()
+                    .filter_map(|input| wallet.get_outpoint_spender(input.prev_output))
+                    .find(|spender| *spender != txid);
+                match replacing_txid {
+                    Some(replacing_txid) => {
+                        self.set_status(TransferStatus::Replaced(replacing_txid))
+                    }
+                    None => self.set_status(TransferStatus::Failed(format!(
+                        "witness TX {txid} is no longer known"
+                    ))),
+                }
+            }
+            _ => {
+                if let TransferStatus::Confirmed(_) = self.status {
+                    let unconfirmed = self
+                        .history
+                        .iter()
+                        .rev()
+                        .find(|s| !matches!(s, TransferStatus::Confirmed(_)))
+                        .cloned()
+                        .unwrap();
+                    self.set_status(unconfirmed);
+                }
+            }
+        }
+        &self.status
+    }
+}
+
 /// RGB asset-specific information to color a transaction
 #[derive(Clone, Debug)]
 pub struct AssetColoringInfo {
@@ -125,6 +697,9 @@ pub struct AssetColoringInfo {
     pub input_outpoints: Vec<Outpoint>,
     /// Map of vouts and asset amounts to color the transaction outputs
     pub output_map: HashMap<u32, u64>,
+    /// Vout receiving the non-fungible input states (e.g. UDA tokens), which would be burned
+    /// otherwise
+    pub nonfungible_vout: Option<u32>,
     /// Static blinding to keep the transaction construction deterministic
     pub static_blinding: Option<u64>,
 }
@@ -138,11 +713,88 @@ pub struct ColoringInfo {
     pub static_blinding: Option<u64>,
     /// Nonce for offchain TXs ordering
     pub nonce: Option<u64>,
+    /// Seal close method used to commit to the RGB data
+    pub close_method: CloseMethod,
 }
 
 /// Map of contract ID and list of its beneficiaries
 pub type AssetBeneficiariesMap = BTreeMap<ContractId, Vec<BuilderSeal<GraphSeal>>>;
 
+/// What a party of an atomic swap gives to its counterparty
+#[derive(Clone, Debug)]
+pub struct SwapOffer {
+    /// Outpoints spent by the party, holding the offered asset (if any) and the BTC
+    pub input_outpoints: Vec<Outpoint>,
+    /// Offered asset, as contract ID, iface and amount
+    pub asset: Option<(ContractId, TypeName, u64)>,
+    /// Offered BTC amount
+    pub btc_amt: u64,
+}
+
+/// Atomic swap TX, colored by both parties but not signed yet
+#[derive(Clone, Debug)]
+pub struct Swap {
+    /// Swap PSBT
+    pub psbt: Psbt,
+    /// RGB data committed in the PSBT
+    pub fascia: Fascia,
+    /// Beneficiaries of the transitions colored by the maker
+    pub maker_beneficiaries: AssetBeneficiariesMap,
+    /// Beneficiaries of the transitions colored by the taker
+    pub taker_beneficiaries: AssetBeneficiariesMap,
+}
+
+/// Payjoin transfer, built by the payer from an invoice and extended by the receiver
+#[derive(Clone, Debug)]
+pub struct Payjoin {
+    /// Payjoin PSBT, colored by the payer only when finalizing
+    pub psbt: Psbt,
+    /// Vout of the receiver output paid by the invoice
+    pub receiver_vout: u32,
+    /// Coloring info of the payer transition
+    pub payer_coloring_info: ColoringInfo,
+    /// Beneficiaries of the receiver transitions carrying forward its input allocations
+    pub receiver_beneficiaries: AssetBeneficiariesMap,
+    /// UTXOs contributed by the receiver
+    pub receiver_inputs: Vec<Outpoint>,
+    /// Vout of the receiver change output
+    pub receiver_change_vout: Option<u32>,
+}
+
+/// Resolver for consignments whose witness TX has not been broadcasted yet
+pub struct OffchainResolver<'a, 'cons, const TRANSFER: bool> {
+    pub witness_id: XWitnessId,
+    pub consignment: &'cons IndexedConsignment<'cons, TRANSFER>,
+    pub fallback: &'a AnyResolver,
+}
+
+impl<const TRANSFER: bool> ResolveWitness for OffchainResolver<'_, '_, TRANSFER> {
+    fn resolve_pub_witness(
+        &self,
+        witness_id: XWitnessId,
+    ) -> Result<XWitnessTx, WitnessResolverError> {
+        self.consignment
+            .pub_witness(witness_id)
+            .and_then(|p| p.map_ref(|pw| pw.tx().cloned()).transpose())
+            .ok_or(WitnessResolverError::Unknown(witness_id))
+            .or_else(|_| self.fallback.resolve_pub_witness(witness_id))
+    }
+    fn resolve_pub_witness_ord(
+        &self,
+        witness_id: XWitnessId,
+    ) -> Result<WitnessOrd, WitnessResolverError> {
+        if witness_id != self.witness_id {
+            return self.fallback.resolve_pub_witness_ord(witness_id);
+        }
+        Ok(WitnessOrd::Tentative)
+    }
+}
+
+/// Asset schemata provided by `rgb-schemata`.
+///
+/// There is no inflatable fungible schema there: the RGB20 interface declares the inflation
+/// operations, but no schema implements them, so secondary issuance can't be tested until one is
+/// added upstream.
 #[derive(Debug, EnumIter, Copy, Clone, PartialEq)]
 pub enum AssetSchema {
     Nia,
@@ -156,8 +808,18 @@ impl fmt::Display for AssetSchema {
     }
 }
 
+impl TryFrom<SchemaId> for AssetSchema {
+    type Error = SchemaId;
+
+    fn try_from(schema_id: SchemaId) -> Result<Self, Self::Error> {
+        AssetSchema::iter()
+            .find(|s| s.schema().schema_id() == schema_id)
+            .ok_or(schema_id)
+    }
+}
+
 impl AssetSchema {
-    fn iface_type_name(&self) -> TypeName {
+    pub fn iface_type_name(&self) -> TypeName {
         tn!(match self {
             Self::Nia => "RGB20Fixed",
             Self::Uda => "RGB21Unique",
@@ -205,17 +867,93 @@ impl AssetSchema {
         }
     }
 
-    fn get_valid_kit(&self) -> ValidKit {
+    pub fn kit(&self) -> Kit {
         let mut kit = Kit::default();
         kit.schemata.push(self.schema()).unwrap();
         kit.ifaces.push(self.iface()).unwrap();
         kit.iimpls.push(self.issue_impl()).unwrap();
         kit.scripts.extend(self.scripts().into_values()).unwrap();
         kit.types = self.types();
-        kit.validate().unwrap()
+        kit
+    }
+
+    fn get_valid_kit(&self) -> ValidKit {
+        self.kit().validate().unwrap()
+    }
+
+    /// Save the kit of this schema to the given path, armored or in binary form
+    pub fn export_kit(&self, path: &Path, armored: bool) {
+        save_file_content(&self.kit(), path, armored);
+    }
+}
+
+/// Save the given kit or consignment to the given path, armored or in binary form
+pub fn save_file_content(content: &impl FileContent, path: &Path, armored: bool) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    if armored {
+        content.save_armored(path).unwrap();
+    } else {
+        content.save_file(path).unwrap();
+    }
+}
+
+/// Load a kit (either armored or in binary form) from the given path
+pub fn load_kit(path: &Path) -> Kit {
+    try_load_kit(path).unwrap()
+}
+
+pub fn try_load_kit(path: &Path) -> Result<Kit, TestError> {
+    if is_armored(path) {
+        Ok(Kit::from_str(&read_armored(path)?).map_err(LoadFailure::Armor)?)
+    } else {
+        Ok(Kit::load_file(path).map_err(LoadFailure::File)?)
+    }
+}
+
+/// Whether the file at the given path holds armored data, by its `.rgba` extension
+fn is_armored(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()) == Some("rgba")
+}
+
+/// Read the armored data at the given path, unfolding the header lines. The armor parser reads
+/// each header from a single line, while the headers with parameters are written over several
+/// ones.
+fn read_armored(path: &Path) -> Result<String, TestError> {
+    let armored = std::fs::read_to_string(path).map_err(LoadFailure::Io)?;
+    Ok(armored.replace("\n\t", " "))
+}
+
+/// Load a contract (either armored or in binary form) from the given path
+pub fn load_contract(path: &Path) -> Contract {
+    try_load_contract(path).unwrap()
+}
+
+pub fn try_load_contract(path: &Path) -> Result<Contract, TestError> {
+    if is_armored(path) {
+        Ok(Contract::from_str(&read_armored(path)?).map_err(LoadFailure::from)?)
+    } else {
+        Ok(Contract::load_file(path).map_err(LoadFailure::File)?)
     }
 }
 
+/// Paths of the kits found in the given directory, with the issuance spec that has the same file
+/// stem (see [`IssuanceSpec`])
+pub fn kit_files(dir: &Path) -> Vec<(PathBuf, PathBuf)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut kit_files: Vec<_> = entries
+        .map(|e| e.unwrap().path())
+        .filter(|p| matches!(p.extension().and_then(|e| e.to_str()), Some("rgb" | "rgba")))
+        .map(|p| {
+            let spec_path = p.with_extension("yaml");
+            (p, spec_path)
+        })
+        .collect();
+    kit_files.sort();
+    kit_files
+}
+
 #[derive(Debug)]
 pub enum AssetInfo {
     Nia {
@@ -227,6 +965,8 @@ pub enum AssetInfo {
         spec: AssetSpec,
         terms: ContractTerms,
         token_data: TokenData,
+        /// Fraction of the token owned by the genesis seal, the UDA schema accepts only 1
+        fraction: u64,
     },
     Cfa {
         name: Name,
@@ -345,6 +1085,7 @@ impl AssetInfo {
             spec,
             terms,
             token_data,
+            fraction: 1,
         }
     }
 
@@ -391,6 +1132,7 @@ impl AssetInfo {
                 spec,
                 terms,
                 token_data,
+                ..
             } => builder
                 .add_global_state("spec", spec.clone())
                 .unwrap()
@@ -433,23 +1175,6 @@ impl AssetInfo {
         close_method: CloseMethod,
         outpoints: Vec<Outpoint>,
     ) -> ContractBuilder {
-        fn get_genesis_seal(
-            close_method: CloseMethod,
-            outpoint: Outpoint,
-        ) -> BuilderSeal<BlindSeal<Txid>> {
-            let blind_seal = match close_method {
-                CloseMethod::TapretFirst => {
-                    BlindSeal::tapret_first_rand(outpoint.txid, outpoint.vout)
-                }
-                CloseMethod::OpretFirst => {
-                    BlindSeal::opret_first_rand(outpoint.txid, outpoint.vout)
-                }
-            };
-            let genesis_seal = GenesisSeal::from(blind_seal);
-            let seal: XChain<BlindSeal<Txid>> = XChain::with(Layer1::Bitcoin, genesis_seal);
-            BuilderSeal::from(seal)
-        }
-
         match self {
             Self::Nia { issue_amounts, .. } | Self::Cfa { issue_amounts, .. } => {
                 for (amt, outpoint) in issue_amounts.iter().zip(outpoints.iter().cycle()) {
@@ -463,8 +1188,12 @@ impl AssetInfo {
                 }
                 builder
             }
-            Self::Uda { token_data, .. } => {
-                let fraction = OwnedFraction::from(1);
+            Self::Uda {
+                token_data,
+                fraction,
+                ..
+            } => {
+                let fraction = OwnedFraction::from(*fraction);
                 let allocation = Allocation::with(token_data.index, fraction);
                 builder
                     .add_data(
@@ -478,33 +1207,239 @@ impl AssetInfo {
     }
 }
 
-pub struct Report {
-    pub report_path: PathBuf,
+/// State entry of an [`IssuanceSpec`]
+#[derive(Debug, Clone)]
+pub struct StateSpec {
+    pub name: FieldName,
+    /// Fully qualified strict type of the value, absent for fungible owned state
+    pub ty: Option<TypeFqn>,
+    pub value: StrictVal,
 }
 
-impl Report {
-    pub fn write_header(&self, fields: &[&str]) {
-        let mut file = std::fs::File::options()
-            .read(true)
-            .write(true)
-            .create_new(true)
-            .open(&self.report_path)
-            .unwrap();
-        file.write_all(format!("{}\n", fields.join(";")).as_bytes())
-            .unwrap();
-    }
+/// Contract issuance described in a YAML file, to issue contracts of schemas loaded from kits on
+/// disk without hardcoding their state in [`AssetInfo`].
+///
+/// ```yaml
+/// iface: RGB20Fixed
+/// global:
+///   - name: spec
+///     type: RGBContract.AssetSpec
+///     value:
+///       ticker: NIATCKR
+///       name: NIA asset name
+///       details: ~
+///       precision: centi
+///   - name: issuedSupply
+///     type: RGBContract.Amount
+///     value: 600
+/// owned:
+///   - name: assetOwner
+///     value: 600
+/// ```
+///
+/// Values are typified against the type system of the kit, so struct fields must be listed in the
+/// order the type declares them and optional fields need an explicit `~`. Owned state without a
+/// type is fungible.
+#[derive(Debug, Clone)]
+pub struct IssuanceSpec {
+    pub iface: TypeName,
+    pub global: Vec<StateSpec>,
+    pub owned: Vec<StateSpec>,
+}
 
-    pub fn write_duration(&self, duration: Duration) {
-        let mut file = OpenOptions::new()
-            .append(true)
-            .open(&self.report_path)
-            .unwrap();
-        file.write_all(format!("{};", duration.as_millis()).as_bytes())
-            .unwrap();
+impl IssuanceSpec {
+    pub fn load(path: &Path) -> Self {
+        let file = std::fs::File::open(path).unwrap();
+        let yaml: serde_yaml::Value = serde_yaml::from_reader(file).unwrap();
+        Self::from_yaml(&yaml)
     }
 
-    pub fn end_line(&self) {
-        let mut file = OpenOptions::new()
+    fn from_yaml(yaml: &serde_yaml::Value) -> Self {
+        fn str_field<'a>(yaml: &'a serde_yaml::Value, field: &str) -> &'a str {
+            yaml[field]
+                .as_str()
+                .unwrap_or_else(|| panic!("missing or invalid field {field} in {yaml:?}"))
+        }
+        fn entries(yaml: &serde_yaml::Value, field: &str) -> Vec<StateSpec> {
+            yaml.get(field)
+                .and_then(|v| v.as_sequence())
+                .cloned()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|entry| StateSpec {
+                    name: FieldName::try_from(str_field(&entry, "name").to_owned()).unwrap(),
+                    ty: entry.get("type").map(|ty| {
+                        let ty = ty.as_str().unwrap();
+                        let (lib, name) = ty
+                            .split_once('.')
+                            .unwrap_or_else(|| panic!("type {ty} is not fully qualified"));
+                        TypeFqn::with(
+                            LibName::try_from(lib.to_owned()).unwrap(),
+                            TypeName::try_from(name.to_owned()).unwrap(),
+                        )
+                    }),
+                    value: StrictVal::from(entry["value"].clone()),
+                })
+                .collect()
+        }
+
+        Self {
+            iface: TypeName::try_from(str_field(yaml, "iface").to_owned()).unwrap(),
+            global: entries(yaml, "global"),
+            owned: entries(yaml, "owned"),
+        }
+    }
+
+    /// Builder issuing this spec from the given kit, with the owned state assigned to the given
+    /// outpoints in turn
+    pub fn contract_builder(
+        &self,
+        kit: &Kit,
+        close_method: CloseMethod,
+        outpoints: Vec<Outpoint>,
+    ) -> Result<ContractBuilder, TestError> {
+        let mut builder = self.builder(kit)?;
+        builder = self.add_global_state(builder, &kit.types)?;
+        self.add_owned_state(builder, &kit.types, close_method, outpoints)
+    }
+
+    /// Builder for this issuance, taking the interface, its implementation and the schema from
+    /// the given kit
+    fn builder(&self, kit: &Kit) -> Result<ContractBuilder, TestError> {
+        let iface = kit
+            .ifaces
+            .iter()
+            .find(|i| i.name == self.iface)
+            .ok_or_else(|| TestError::UnknownIface(self.iface.clone()))?;
+        let iimpl = kit
+            .iimpls
+            .iter()
+            .find(|i| i.iface_id == iface.iface_id())
+            .ok_or_else(|| TestError::UnknownIface(self.iface.clone()))?;
+        let schema = kit
+            .schemata
+            .iter()
+            .find(|s| s.schema_id() == iimpl.schema_id)
+            .ok_or_else(|| TestError::UnknownIface(self.iface.clone()))?;
+        let scripts = Scripts::from_iter_checked(kit.scripts.iter().map(|l| (l.id(), l.clone())));
+        Ok(ContractBuilder::with(
+            Identity::default(),
+            iface.clone(),
+            schema.clone(),
+            iimpl.clone(),
+            kit.types.clone(),
+            scripts,
+        ))
+    }
+
+    fn add_global_state(
+        &self,
+        mut builder: ContractBuilder,
+        types: &TypeSystem,
+    ) -> Result<ContractBuilder, TestError> {
+        for state in &self.global {
+            let value = state.serialize(types)?;
+            builder = builder.add_global_state(state.name.clone(), value)?;
+        }
+        Ok(builder)
+    }
+
+    fn add_owned_state(
+        &self,
+        mut builder: ContractBuilder,
+        types: &TypeSystem,
+        close_method: CloseMethod,
+        outpoints: Vec<Outpoint>,
+    ) -> Result<ContractBuilder, TestError> {
+        for (state, outpoint) in self.owned.iter().zip(outpoints.iter().cycle()) {
+            let seal = get_genesis_seal(close_method, *outpoint);
+            let name = state.name.clone();
+            builder = match (&state.ty, &state.value) {
+                (None, StrictVal::Number(StrictNum::Uint(amount))) => {
+                    builder.add_fungible_state(name, seal, *amount)
+                }
+                (None, value) => return Err(SpecError::InvalidAmount(name, value.clone()).into()),
+                (Some(_), _) => builder.add_data(name, seal, state.serialize(types)?),
+            }?;
+        }
+        Ok(builder)
+    }
+}
+
+impl StateSpec {
+    /// Strict serialization of the value, typified against the given type system
+    fn serialize(&self, types: &TypeSystem) -> Result<impl StrictSerialize, TestError> {
+        let ty = self
+            .ty
+            .as_ref()
+            .ok_or_else(|| SpecError::MissingType(self.name.clone()))?;
+        let sem_id = *STATE_TYPES
+            .resolve(ty.clone())
+            .ok_or_else(|| SpecError::UnknownType(ty.clone()))?;
+        let typed = types
+            .typify(self.value.clone(), sem_id)
+            .map_err(|e| SpecError::Typify(self.name.clone(), e))?;
+        // the builder takes the value as already serialized data
+        #[allow(deprecated)]
+        types
+            .strict_serialize_type::<{ u16::MAX as usize }>(&typed)
+            .map_err(|e| SpecError::Serialize(self.name.clone(), e).into())
+    }
+}
+
+/// Names of the strict types used by the state of the standard interfaces
+static STATE_TYPES: Lazy<SymbolicSys> = Lazy::new(|| {
+    let libs = [
+        std_stl(),
+        bp_tx_stl(),
+        rgb_contract_stl(),
+        Rgb21::NONE.stl(),
+    ];
+    libs.into_iter()
+        .fold(SystemBuilder::new(), |builder, lib| {
+            builder.import(lib).unwrap()
+        })
+        .finalize()
+        .unwrap()
+});
+
+fn get_genesis_seal(close_method: CloseMethod, outpoint: Outpoint) -> BuilderSeal<BlindSeal<Txid>> {
+    let blind_seal = match close_method {
+        CloseMethod::TapretFirst => BlindSeal::tapret_first_rand(outpoint.txid, outpoint.vout),
+        CloseMethod::OpretFirst => BlindSeal::opret_first_rand(outpoint.txid, outpoint.vout),
+    };
+    let genesis_seal = GenesisSeal::from(blind_seal);
+    let seal: XChain<BlindSeal<Txid>> = XChain::with(Layer1::Bitcoin, genesis_seal);
+    BuilderSeal::from(seal)
+}
+
+pub struct Report {
+    pub report_path: PathBuf,
+}
+
+impl Report {
+    pub fn write_header(&self, fields: &[&str]) {
+        let mut file = std::fs::File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&self.report_path)
+            .unwrap();
+        file.write_all(format!("{}\n", fields.join(";")).as_bytes())
+            .unwrap();
+    }
+
+    pub fn write_duration(&self, duration: Duration) {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&self.report_path)
+            .unwrap();
+        file.write_all(format!("{};", duration.as_millis()).as_bytes())
+            .unwrap();
+    }
+
+    pub fn end_line(&self) {
+        let mut file = OpenOptions::new()
             .append(true)
             .open(&self.report_path)
             .unwrap();
@@ -576,6 +1511,9 @@ fn _get_wallet(
         signer,
         wallet_dir,
         instance,
+        allow_burns: false,
+        consignment_format: ConsignmentFormat::default(),
+        seed: None,
     };
 
     // TODO: remove if once found solution for esplora 'Too many requests' error
@@ -590,24 +1528,117 @@ pub fn get_wallet(descriptor_type: &DescriptorType) -> TestWallet {
     get_wallet_custom(descriptor_type, INSTANCE_1)
 }
 
-pub fn get_wallet_custom(descriptor_type: &DescriptorType, instance: u8) -> TestWallet {
+fn random_seed() -> Vec<u8> {
     let mut seed = vec![0u8; 128];
     rand::thread_rng().fill_bytes(&mut seed);
+    seed
+}
 
-    let xpriv_account = XprivAccount::with_seed(true, &seed).derive(h![86, 1, 0]);
+fn get_xpriv_account(seed: &[u8]) -> (XprivAccount, PathBuf) {
+    let xpriv_account = XprivAccount::with_seed(true, seed).derive(h![86, 1, 0]);
 
     let fingerprint = xpriv_account.account_fp().to_string();
     let wallet_dir = PathBuf::from(TEST_DATA_DIR)
         .join(INTEGRATION_DATA_DIR)
         .join(fingerprint);
+    (xpriv_account, wallet_dir)
+}
 
-    _get_wallet(
+pub fn get_wallet_custom(descriptor_type: &DescriptorType, instance: u8) -> TestWallet {
+    let seed = random_seed();
+    let (xpriv_account, wallet_dir) = get_xpriv_account(&seed);
+
+    let mut wallet = _get_wallet(
         descriptor_type,
         Network::Regtest,
         wallet_dir,
         WalletAccount::Private(xpriv_account),
         instance,
-    )
+    );
+    wallet.seed = Some(seed);
+    wallet
+}
+
+/// Restore a wallet from its seed in a new directory, re-importing the tapret tweaks, the
+/// blinded seals and the consignments (including the ones revealing the change of its transfers)
+/// found in the backup directory (i.e. the directory of the original wallet).
+pub fn restore_wallet(
+    descriptor_type: &DescriptorType,
+    seed: &[u8],
+    backup_dir: &Path,
+) -> TestWallet {
+    let (xpriv_account, wallet_dir) = get_xpriv_account(seed);
+
+    let mut wallet = _get_wallet(
+        descriptor_type,
+        Network::Regtest,
+        wallet_dir.with_extension("restored"),
+        WalletAccount::Private(xpriv_account),
+        INSTANCE_1,
+    );
+    wallet.seed = Some(seed.to_vec());
+
+    // tweaks are needed to recognize the tapret hosts when syncing
+    if let Ok(entries) = std::fs::read_dir(backup_dir.join("tweaks")) {
+        for entry in entries {
+            let file = std::fs::File::open(entry.unwrap().path()).unwrap();
+            let (terminal, tapret_commitment): (Terminal, TapretCommitment) =
+                serde_yaml::from_reader(file).unwrap();
+            wallet.add_tapret_tweak(terminal, tapret_commitment);
+        }
+    }
+    wallet.sync();
+
+    if let Ok(entries) = std::fs::read_dir(backup_dir.join("seals")) {
+        for entry in entries {
+            let file = std::fs::File::open(entry.unwrap().path()).unwrap();
+            let seal: XChain<GraphSeal> = serde_yaml::from_reader(file).unwrap();
+            wallet.wallet.stock_mut().store_secret_seal(seal).unwrap();
+        }
+    }
+
+    let resolver = wallet.get_resolver();
+    let entries = ["consignments", "change"]
+        .into_iter()
+        .filter_map(|dir| std::fs::read_dir(backup_dir.join(dir)).ok())
+        .flatten();
+    for entry in entries {
+        let path = entry.unwrap().path();
+        let file = std::fs::File::open(&path).unwrap();
+        let consignment: Transfer = serde_yaml::from_reader(file).unwrap();
+        // consignments whose witness TXs are no longer valid (e.g. never broadcasted)
+        match consignment.validate(&resolver, wallet.testnet()) {
+            Ok(validated_consignment)
+                if validated_consignment.validation_status().validity() == Validity::Valid =>
+            {
+                wallet
+                    .wallet
+                    .stock_mut()
+                    .accept_transfer(validated_consignment, &resolver)
+                    .unwrap();
+            }
+            _ => println!("skipping invalid consignment {path:?}"),
+        }
+    }
+
+    wallet
+}
+
+/// Watch-only wallet and the external signer holding its keys
+pub fn get_watch_only_wallet(descriptor_type: &DescriptorType) -> (TestWallet, TestSigner) {
+    let (xpriv_account, wallet_dir) = get_xpriv_account(&random_seed());
+
+    let wallet = _get_wallet(
+        descriptor_type,
+        Network::Regtest,
+        wallet_dir,
+        WalletAccount::Public(xpriv_account.to_xpub_account()),
+        INSTANCE_1,
+    );
+    let signer = TestSigner {
+        signer: TestnetSigner::new(xpriv_account),
+    };
+    (wallet, signer)
 }
 
 pub fn get_mainnet_wallet() -> TestWallet {
@@ -647,15 +1678,24 @@ fn get_resolver(indexer_url: &str) -> AnyResolver {
 }
 
 fn broadcast_tx(tx: &Tx, indexer_url: &str) {
+    try_broadcast_tx(tx, indexer_url).unwrap();
+}
+
+fn try_broadcast_tx(tx: &Tx, indexer_url: &str) -> Result<(), TestError> {
     match get_indexer(indexer_url) {
         AnyIndexer::Electrum(inner) => {
-            inner.transaction_broadcast(tx).unwrap();
+            inner
+                .transaction_broadcast(tx)
+                .map_err(|e| TestError::Indexer(e.into()))?;
         }
         AnyIndexer::Esplora(inner) => {
-            inner.publish(tx).unwrap();
+            inner
+                .publish(tx)
+                .map_err(|e| TestError::Indexer(e.into()))?;
         }
         _ => unreachable!("unsupported indexer"),
     }
+    Ok(())
 }
 
 pub fn broadcast_tx_and_mine(tx: &Tx, instance: u8) {
@@ -680,12 +1720,26 @@ pub fn attachment_from_fpath(fpath: &str) -> Attachment {
 }
 
 fn uda_token_data_minimal() -> TokenData {
+    uda_token_data_with_index(UDA_FIXED_INDEX)
+}
+
+pub fn uda_token_data_with_index(token_index: u32) -> TokenData {
     TokenData {
-        index: TokenIndex::from(UDA_FIXED_INDEX),
+        index: TokenIndex::from(token_index),
         ..Default::default()
     }
 }
 
+/// Hex of the data state of the whole UDA token with the given index
+pub fn uda_allocation_state(token_index: u32) -> String {
+    token_index
+        .to_le_bytes()
+        .iter()
+        .chain(1u64.to_le_bytes().iter())
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
 pub fn uda_token_data(
     ticker: &str,
     name: &str,
@@ -706,6 +1760,124 @@ pub fn uda_token_data(
     token_data
 }
 
+/// Replace a character in the middle of the armored data, skipping line breaks
+pub fn alter_armored_body(mut bytes: Vec<u8>) -> Vec<u8> {
+    let mut idx = bytes.len() / 2;
+    while bytes[idx] == b'\n' {
+        idx += 1;
+    }
+    bytes[idx] = if bytes[idx] == b'A' { b'B' } else { b'A' };
+    bytes
+}
+
+/// Change the checksum header of the armored data, leaving the data untouched
+pub fn tamper_armor_checksum(bytes: Vec<u8>) -> Vec<u8> {
+    let armored = String::from_utf8(bytes).unwrap();
+    let (before, after) = armored
+        .split_once("Check-SHA256: ")
+        .expect("armored data without checksum");
+    let mut checksum = after.chars();
+    let first = checksum.next().unwrap();
+    let tampered = if first == '0' { '1' } else { '0' };
+    format!("{before}Check-SHA256: {tampered}{}", checksum.as_str()).into_bytes()
+}
+
+/// Fee rate (sat/vB) of the given TX paying the given fee
+pub fn tx_fee_rate(tx: &Tx, fee: u64) -> f64 {
+    fee as f64 / tx.vbytes().to_u32() as f64
+}
+
+/// Beneficiaries of the given asset beneficiaries map that are assigned to the given vout
+pub fn beneficiaries_for_vout(
+    asset_beneficiaries: &AssetBeneficiariesMap,
+    vout: u32,
+) -> AssetBeneficiariesMap {
+    asset_beneficiaries
+        .iter()
+        .map(|(contract_id, seals)| {
+            let seals = seals
+                .iter()
+                .filter(|seal| {
+                    matches!(seal, BuilderSeal::Revealed(seal)
+                        if seal.as_reduced_unsafe().vout.into_u32() == vout)
+                })
+                .cloned()
+                .collect();
+            (*contract_id, seals)
+        })
+        .collect()
+}
+
+/// Amount assigned by the terminal transitions of the consignment to the seals accepted by the
+/// given filter (receiving the concealed seal and, when revealed, the vout of the witness TX), or
+/// `None` if the consignment assigns nothing to them
+pub fn terminal_amount(
+    consignment: &Transfer,
+    is_beneficiary: impl Fn(SecretSeal, Option<u32>) -> bool,
+) -> Option<u64> {
+    let mut amount = None;
+    for bundle in consignment
+        .bundled_witnesses()
+        .flat_map(|bw| bw.anchored_bundles.bundles())
+    {
+        let Some(terminal) = consignment.terminals.get(&bundle.bundle_id()) else {
+            continue;
+        };
+        let terminal_seal = *terminal.as_reduced_unsafe();
+        for transition in bundle.known_transitions.values() {
+            for assigns in transition.assignments.values() {
+                match assigns {
+                    TypedAssigns::Fungible(assigns) => {
+                        for assign in assigns {
+                            let seal = *assign.to_confidential_seal().as_reduced_unsafe();
+                            let vout = assign
+                                .revealed_seal()
+                                .map(|s| s.as_reduced_unsafe().vout.into_u32());
+                            if seal == terminal_seal && is_beneficiary(seal, vout) {
+                                let value = assign
+                                    .as_revealed_state()
+                                    .map(|state| state.value.as_u64())
+                                    .unwrap_or_default();
+                                *amount.get_or_insert(0) += value;
+                            }
+                        }
+                    }
+                    TypedAssigns::Structured(assigns) => {
+                        for assign in assigns {
+                            let seal = *assign.to_confidential_seal().as_reduced_unsafe();
+                            let vout = assign
+                                .revealed_seal()
+                                .map(|s| s.as_reduced_unsafe().vout.into_u32());
+                            if seal == terminal_seal && is_beneficiary(seal, vout) {
+                                amount.get_or_insert(0);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    amount
+}
+
+/// Check that the amount owned by the given wallets matches the issued supply
+pub fn assert_no_inflation(
+    wallets: &[&TestWallet],
+    contract_id: ContractId,
+    iface_type_name: &TypeName,
+    issued_supply: u64,
+) {
+    let owned_amt: u64 = wallets
+        .iter()
+        .map(|wlt| wlt.contract_fungible_owned_amount(contract_id, iface_type_name))
+        .sum();
+    assert_eq!(
+        owned_amt, issued_supply,
+        "owned amount differs from the issued supply"
+    );
+}
+
 impl TestWallet {
     pub fn network(&self) -> Network {
         self.wallet.wallet().network()
@@ -715,10 +1887,34 @@ impl TestWallet {
         self.network().is_testnet()
     }
 
+    pub fn descriptor_type(&self) -> DescriptorType {
+        match self.close_method() {
+            CloseMethod::OpretFirst => DescriptorType::Wpkh,
+            CloseMethod::TapretFirst => DescriptorType::Tr,
+        }
+    }
+
+    pub fn seed(&self) -> &[u8] {
+        self.seed.as_ref().expect("wallet seed is unknown")
+    }
+
+    pub fn wallet_dir(&self) -> &Path {
+        &self.wallet_dir
+    }
+
     pub fn keychain(&self) -> RgbKeychain {
         RgbKeychain::for_method(self.close_method())
     }
 
+    /// Account key of the wallet descriptor
+    pub fn xpub_derivable(&self) -> XpubDerivable {
+        self.descriptor
+            .keys()
+            .next()
+            .expect("descriptor should have a key")
+            .clone()
+    }
+
     pub fn get_derived_address(&self) -> DerivedAddr {
         self.wallet
             .wallet()
@@ -781,6 +1977,10 @@ impl TestWallet {
         broadcast_tx(tx, &self.indexer_url());
     }
 
+    pub fn try_broadcast_tx(&self, tx: &Tx) -> Result<(), TestError> {
+        try_broadcast_tx(tx, &self.indexer_url())
+    }
+
     pub fn get_witness_ord(&self, txid: &Txid) -> WitnessOrd {
         self.get_resolver()
             .resolve_pub_witness_ord(XWitnessId::Bitcoin(*txid))
@@ -794,13 +1994,52 @@ impl TestWallet {
         }
     }
 
+    /// ID of the TX spending the given output, `None` if it's unspent
+    pub fn get_outpoint_spender(&self, outpoint: Outpoint) -> Option<Txid> {
+        match self.get_indexer() {
+            AnyIndexer::Electrum(inner) => {
+                let prev_tx = inner.transaction_get(&outpoint.txid).unwrap();
+                let script_pubkey = &prev_tx.outputs[outpoint.vout.to_usize()].script_pubkey;
+                inner
+                    .script_get_history(script_pubkey)
+                    .unwrap()
+                    .into_iter()
+                    .map(|h| h.tx_hash)
+                    .find(|txid| {
+                        inner
+                            .transaction_get(txid)
+                            .unwrap()
+                            .inputs
+                            .iter()
+                            .any(|input| input.prev_output == outpoint)
+                    })
+            }
+            AnyIndexer::Esplora(inner) => inner
+                .output_status(&outpoint.txid, outpoint.vout.to_u32() as u64)
+                .unwrap()
+                .and_then(|status| status.txid),
+            _ => unreachable!("unsupported indexer"),
+        }
+    }
+
+    /// Number of confirmations of the TX, `None` if not mined
+    pub fn get_tx_confirmations(&self, txid: &Txid) -> Option<u32> {
+        self.get_tx_height(txid)
+            .map(|height| get_height_custom(self.instance).saturating_sub(height) + 1)
+    }
+
     pub fn sync(&mut self) {
+        self.try_sync().unwrap();
+    }
+
+    pub fn try_sync(&mut self) -> Result<(), TestError> {
         let indexer = self.get_indexer();
         self.wallet
             .wallet_mut()
             .update(&indexer)
             .into_result()
-            .unwrap();
+            .map_err(TestError::Sync)?;
+        Ok(())
     }
 
     pub fn close_method(&self) -> CloseMethod {
@@ -827,14 +2066,17 @@ impl TestWallet {
         close_method: CloseMethod,
         outpoints: Vec<Option<Outpoint>>,
     ) -> (ContractId, TypeName) {
-        let outpoints = if outpoints.is_empty() {
-            vec![self.get_utxo(None)]
-        } else {
-            outpoints
-                .into_iter()
-                .map(|o| o.unwrap_or_else(|| self.get_utxo(None)))
-                .collect()
-        };
+        self.try_issue_with_info(asset_info, close_method, outpoints)
+            .unwrap()
+    }
+
+    pub fn try_issue_with_info(
+        &mut self,
+        asset_info: AssetInfo,
+        close_method: CloseMethod,
+        outpoints: Vec<Option<Outpoint>>,
+    ) -> Result<(ContractId, TypeName), TestError> {
+        let outpoints = self.genesis_outpoints(outpoints);
 
         let mut builder = ContractBuilder::with(
             Identity::default(),
@@ -849,14 +2091,92 @@ impl TestWallet {
 
         builder = asset_info.add_asset_owner(builder, close_method, outpoints);
 
-        let contract = builder.issue_contract().expect("failure issuing contract");
+        let contract_id = self.issue_and_import(builder)?;
+
+        Ok((contract_id, asset_info.iface_type_name()))
+    }
+
+    pub fn issue_with_spec(
+        &mut self,
+        kit: &Kit,
+        spec: &IssuanceSpec,
+        close_method: CloseMethod,
+        outpoints: Vec<Option<Outpoint>>,
+    ) -> (ContractId, TypeName) {
+        self.try_issue_with_spec(kit, spec, close_method, outpoints)
+            .unwrap()
+    }
+
+    /// Issue a contract of a schema from the given kit, with the state described by the spec. The
+    /// kit is imported in the stock first.
+    pub fn try_issue_with_spec(
+        &mut self,
+        kit: &Kit,
+        spec: &IssuanceSpec,
+        close_method: CloseMethod,
+        outpoints: Vec<Option<Outpoint>>,
+    ) -> Result<(ContractId, TypeName), TestError> {
+        self.try_import_kit(kit.clone())?;
+
+        let outpoints = self.genesis_outpoints(outpoints);
+
+        let builder = spec.contract_builder(kit, close_method, outpoints)?;
+
+        let contract_id = self.issue_and_import(builder)?;
+
+        Ok((contract_id, spec.iface.clone()))
+    }
+
+    fn genesis_outpoints(&mut self, outpoints: Vec<Option<Outpoint>>) -> Vec<Outpoint> {
+        if outpoints.is_empty() {
+            vec![self.get_utxo(None)]
+        } else {
+            outpoints
+                .into_iter()
+                .map(|o| o.unwrap_or_else(|| self.get_utxo(None)))
+                .collect()
+        }
+    }
+
+    fn issue_and_import(&mut self, builder: ContractBuilder) -> Result<ContractId, TestError> {
+        // issuing validates the contract already
+        let contract = builder.issue_contract()?;
+        let contract_id = contract.contract_id();
         let resolver = self.get_resolver();
         self.wallet
             .stock_mut()
-            .import_contract(contract.clone(), resolver)
-            .unwrap();
+            .import_contract(contract, resolver)?;
+        Ok(contract_id)
+    }
+
+    /// Export the contract genesis and its known history from the stock
+    pub fn export_contract(&self, contract_id: ContractId) -> Contract {
+        self.wallet.stock().export_contract(contract_id).unwrap()
+    }
 
-        (contract.contract_id(), asset_info.iface_type_name())
+    pub fn import_contract(&mut self, contract: Contract) {
+        self.try_import_contract(contract).unwrap();
+    }
+
+    /// Import the contract in the stock, validating it first
+    pub fn try_import_contract(&mut self, contract: Contract) -> Result<(), TestError> {
+        let resolver = self.get_resolver();
+        let valid_contract = contract
+            .validate(&resolver, self.testnet())
+            .map_err(|(status, _)| TestError::Validation(status))?;
+        self.wallet
+            .stock_mut()
+            .import_contract(valid_contract, resolver)?;
+        Ok(())
+    }
+
+    /// Import the kit in the stock, validating it first
+    pub fn try_import_kit(&mut self, kit: Kit) -> Result<(), TestError> {
+        let valid_kit = kit
+            .validate()
+            .map_err(|(status, _)| TestError::Validation(status))?;
+        self.wallet.stock_mut().import_kit(valid_kit)?;
+        Ok(())
     }
 
     pub fn issue_nia(
@@ -878,6 +2198,29 @@ impl TestWallet {
         self.issue_with_info(asset_info, close_method, vec![outpoint.copied()])
     }
 
+    /// Issue a collection of UDA tokens with the given indexes. The UDA schema allows a single
+    /// token per contract, so each token is issued as a separate contract.
+    pub fn issue_uda_collection(
+        &mut self,
+        close_method: CloseMethod,
+        token_indexes: &[u32],
+    ) -> Vec<(ContractId, TypeName)> {
+        token_indexes
+            .iter()
+            .map(|token_index| {
+                let asset_info = AssetInfo::uda(
+                    "UDATCKR",
+                    &format!("UDA collection token {token_index}"),
+                    None,
+                    "UDA terms",
+                    None,
+                    uda_token_data_with_index(*token_index),
+                );
+                self.issue_with_info(asset_info, close_method, vec![])
+            })
+            .collect()
+    }
+
     pub fn issue_cfa(
         &mut self,
         issued_supply: u64,
@@ -888,16 +2231,12 @@ impl TestWallet {
         self.issue_with_info(asset_info, close_method, vec![outpoint.copied()])
     }
 
-    pub fn invoice(
+    fn invoice_beneficiary(
         &mut self,
-        contract_id: ContractId,
-        iface_type_name: &TypeName,
-        amount: u64,
         close_method: CloseMethod,
         invoice_type: InvoiceType,
-    ) -> RgbInvoice {
-        let network = self.wallet.wallet().network();
-        let beneficiary = match invoice_type {
+    ) -> Beneficiary {
+        match invoice_type {
             InvoiceType::Blinded(outpoint) => {
                 let outpoint = if let Some(outpoint) = outpoint {
                     outpoint
@@ -910,6 +2249,7 @@ impl TestWallet {
                     outpoint.vout,
                 ));
                 self.wallet.stock_mut().store_secret_seal(seal).unwrap();
+                self.save_seal(seal);
                 Beneficiary::BlindedSeal(*seal.to_secret_seal().as_reduced_unsafe())
             }
             InvoiceType::Witness => {
@@ -919,72 +2259,385 @@ impl TestWallet {
                     method: close_method,
                 })
             }
-        };
+        }
+    }
 
-        let mut builder = RgbInvoiceBuilder::new(XChainNet::bitcoin(network, beneficiary))
-            .set_contract(contract_id)
-            .set_interface(iface_type_name.clone());
+    pub fn invoice(
+        &mut self,
+        contract_id: ContractId,
+        iface_type_name: &TypeName,
+        amount: u64,
+        close_method: CloseMethod,
+        invoice_type: InvoiceType,
+    ) -> RgbInvoice {
         if *iface_type_name == AssetSchema::Uda.iface_type_name() {
             if amount != 1 {
                 panic!("UDA amount must be 1");
             }
-            builder = builder
-                .clone()
-                .set_allocation(UDA_FIXED_INDEX, amount)
-                .unwrap();
-        } else {
-            builder = builder.clone().set_amount_raw(amount);
+            return self.invoice_uda(contract_id, UDA_FIXED_INDEX, close_method, invoice_type);
         }
-        builder.finish()
-    }
-
-    pub fn sign_finalize(&self, psbt: &mut Psbt) {
-        let _sig_count = psbt.sign(self.signer.as_ref().unwrap()).unwrap();
-        psbt.finalize(&self.descriptor);
-    }
 
-    pub fn sign_finalize_extract(&self, psbt: &mut Psbt) -> Tx {
-        self.sign_finalize(psbt);
-        psbt.extract().unwrap()
+        let network = self.wallet.wallet().network();
+        let beneficiary = self.invoice_beneficiary(close_method, invoice_type);
+        RgbInvoiceBuilder::new(XChainNet::bitcoin(network, beneficiary))
+            .set_contract(contract_id)
+            .set_interface(iface_type_name.clone())
+            .set_amount_raw(amount)
+            .finish()
     }
 
-    pub fn transfer(
+    /// Invoice for the whole UDA token with the given index
+    pub fn invoice_uda(
         &mut self,
-        invoice: RgbInvoice,
-        sats: Option<u64>,
-        fee: Option<u64>,
-        broadcast: bool,
+        contract_id: ContractId,
+        token_index: u32,
+        close_method: CloseMethod,
+        invoice_type: InvoiceType,
+    ) -> RgbInvoice {
+        let network = self.wallet.wallet().network();
+        let beneficiary = self.invoice_beneficiary(close_method, invoice_type);
+        RgbInvoiceBuilder::new(XChainNet::bitcoin(network, beneficiary))
+            .set_contract(contract_id)
+            .set_interface(AssetSchema::Uda.iface_type_name())
+            .set_allocation(token_index, 1)
+            .unwrap()
+            .finish()
+    }
+
+    /// Allow signing PSBTs that would burn assets, which otherwise makes `sign_finalize` panic
+    pub fn set_allow_burns(&mut self, allow_burns: bool) {
+        self.allow_burns = allow_burns;
+    }
+
+    /// Wire format of the consignments delivered to receivers by [`TestWallet::send_to_invoice`]
+    pub fn set_consignment_format(&mut self, consignment_format: ConsignmentFormat) {
+        self.consignment_format = consignment_format;
+    }
+
+    /// Serialize the consignment in the wallet wire format and deserialize it as the receiver
+    /// would do
+    pub fn deliver_consignment(&self, consignment: &Transfer) -> Transfer {
+        let bytes = self.consignment_format.serialize(consignment);
+        self.consignment_format.deserialize(&bytes).unwrap()
+    }
+
+    /// Known contracts having allocations on PSBT inputs not spent by any of its transitions
+    pub fn psbt_burned_assets(&self, psbt: &Psbt) -> Vec<(Outpoint, ContractId)> {
+        let mut burned = vec![];
+        for input in psbt.inputs() {
+            let outpoint = input.previous_outpoint;
+            for contract_id in self.utxo_contracts(outpoint) {
+                if input.rgb_consumer(contract_id).unwrap().is_none() {
+                    burned.push((outpoint, contract_id));
+                }
+            }
+        }
+        burned
+    }
+
+    /// Known contracts having allocations on the given outpoint
+    pub fn utxo_contracts(&self, outpoint: Outpoint) -> Vec<ContractId> {
+        let stock = self.wallet.stock();
+        self.list_contracts()
+            .into_iter()
+            .map(|info| info.id)
+            .filter(|contract_id| {
+                stock
+                    .contract_assignments_for(
+                        *contract_id,
+                        [XOutpoint::from(XChain::Bitcoin(outpoint))],
+                    )
+                    .unwrap()
+                    .values()
+                    .any(|opout_state_map| !opout_state_map.is_empty())
+            })
+            .collect()
+    }
+
+    /// Select UTXOs covering the given amount, following the given policy
+    pub fn select_coins(&self, sats: u64, coin_selection: CoinSelection) -> Vec<Outpoint> {
+        let mut utxos = self.utxos();
+        utxos.sort_by_key(|u| std::cmp::Reverse(u.value));
+        let (colored, plain): (Vec<_>, Vec<_>) = utxos
+            .into_iter()
+            .partition(|u| !self.utxo_contracts(u.outpoint).is_empty());
+        let candidates = match coin_selection {
+            CoinSelection::LargestFirst => {
+                let mut candidates = [colored, plain].concat();
+                candidates.sort_by_key(|u| std::cmp::Reverse(u.value));
+                candidates
+            }
+            CoinSelection::PreferPlain => [plain, colored].concat(),
+            CoinSelection::PlainOnly => plain,
+        };
+
+        let mut selected = vec![];
+        let mut selected_sats = 0;
+        for utxo in candidates {
+            if selected_sats >= sats {
+                break;
+            }
+            selected_sats += utxo.value.sats();
+            selected.push(utxo.outpoint);
+        }
+        if selected_sats < sats {
+            panic!("insufficient funds using {coin_selection:?} coin selection");
+        }
+        selected
+    }
+
+    fn check_burns(&self, psbt: &Psbt) -> Result<(), TestError> {
+        let burned = self.psbt_burned_assets(psbt);
+        if !burned.is_empty() {
+            if self.allow_burns {
+                println!("signing PSBT burning assets: {burned:?}");
+            } else {
+                return Err(TestError::Burn(burned));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn sign_finalize(&self, psbt: &mut Psbt) {
+        self.try_sign_finalize(psbt).unwrap();
+    }
+
+    /// Add the wallet signatures to the PSBT without finalizing it, e.g. when some inputs also
+    /// need the signatures of other wallets
+    pub fn sign(&self, psbt: &mut Psbt) {
+        self.try_sign(psbt).unwrap();
+    }
+
+    pub fn try_sign(&self, psbt: &mut Psbt) -> Result<(), TestError> {
+        self.check_burns(psbt)?;
+        let signer = self.signer.as_ref().ok_or(TestError::WatchOnly)?;
+        let _sig_count = psbt.sign(signer).map_err(PsbtError::from)?;
+        Ok(())
+    }
+
+    pub fn try_sign_finalize(&self, psbt: &mut Psbt) -> Result<(), TestError> {
+        self.try_sign(psbt)?;
+        psbt.finalize(&self.descriptor);
+        Ok(())
+    }
+
+    pub fn sign_finalize_extract(&self, psbt: &mut Psbt) -> Tx {
+        self.try_sign_finalize_extract(psbt).unwrap()
+    }
+
+    pub fn try_sign_finalize_extract(&self, psbt: &mut Psbt) -> Result<Tx, TestError> {
+        self.try_sign_finalize(psbt)?;
+        Ok(psbt.extract().map_err(PsbtError::from)?)
+    }
+
+    /// Serialize the PSBT to be signed by an external signer
+    pub fn export_psbt(&self, psbt: &Psbt) -> Vec<u8> {
+        self.check_burns(psbt).unwrap();
+        psbt.serialize(psbt.version)
+    }
+
+    /// Deserialize a PSBT signed by an external signer
+    pub fn import_psbt(&self, psbt_bytes: &[u8]) -> Psbt {
+        Psbt::deserialize(psbt_bytes).unwrap()
+    }
+
+    pub fn finalize_extract(&self, psbt: &mut Psbt) -> Tx {
+        psbt.finalize(&self.descriptor);
+        psbt.extract().unwrap()
+    }
+
+    /// Create the transfer PSBT and consignment, leaving the PSBT unsigned
+    pub fn transfer_unsigned(
+        &mut self,
+        invoice: RgbInvoice,
+        sats: Option<u64>,
+        fee: Option<u64>,
         report: Option<&Report>,
-    ) -> (Transfer, Tx) {
-        self.sync();
+    ) -> (Transfer, Psbt) {
+        self.try_transfer_unsigned(invoice, sats, fee, report)
+            .unwrap()
+    }
+
+    pub fn try_transfer_unsigned(
+        &mut self,
+        invoice: RgbInvoice,
+        sats: Option<u64>,
+        fee: Option<u64>,
+        report: Option<&Report>,
+    ) -> Result<(Transfer, Psbt), TestError> {
+        self.check_invoice_network(&invoice)?;
+        self.try_sync()?;
 
         let fee = Sats::from_sats(fee.unwrap_or(DEFAULT_FEE_ABS));
         let sats = Sats::from_sats(sats.unwrap_or(2000));
         let params = TransferParams::with(fee, sats);
         let pay_start = Instant::now();
-        let (mut psbt, _psbt_meta, consignment) = self.wallet.pay(&invoice, params).unwrap();
+        let (psbt, _psbt_meta, consignment) = self.wallet.pay(&invoice, params)?;
         let pay_duration = pay_start.elapsed();
         if let Some(report) = report {
             report.write_duration(pay_duration);
         }
 
-        let mut cs_path = self.wallet_dir.join("consignments");
+        self.save_consignment(&consignment);
+        self.save_change_consignments(&psbt);
+        if let Some(output) = psbt.outputs().find(|o| o.is_tapret_host()) {
+            if let Some(terminal) = output.terminal_derivation() {
+                let tapret_commitment = output.tapret_commitment().unwrap();
+                self.save_tapret_tweak(psbt.txid(), terminal, tapret_commitment);
+            }
+        }
+
+        Ok((consignment, psbt))
+    }
+
+    /// Check the invoice network, which `RgbWallet::pay` doesn't compare with the wallet one
+    fn check_invoice_network(&self, invoice: &RgbInvoice) -> Result<(), TestError> {
+        let chain_net = invoice.beneficiary.chain_network();
+        if chain_net != XChainNet::bitcoin(self.network(), ()).chain_network() {
+            return Err(TestError::WrongNetwork(chain_net));
+        }
+        Ok(())
+    }
+
+    /// Save the blinded seal in the wallet directory, to be able to restore it
+    fn save_seal(&self, seal: XChain<GraphSeal>) {
+        let mut seal_path = self.wallet_dir.join("seals");
+        std::fs::create_dir_all(&seal_path).unwrap();
+        seal_path.push(seal.to_secret_seal().to_string());
+        seal_path.set_extension("yaml");
+        let file = std::fs::File::create(seal_path).unwrap();
+        serde_yaml::to_writer(file, &seal).unwrap();
+    }
+
+    /// Consignments saved in the wallet directory, both sent and received
+    pub fn saved_consignments(&self) -> Vec<Transfer> {
+        let Ok(entries) = std::fs::read_dir(self.wallet_dir.join("consignments")) else {
+            return vec![];
+        };
+        let mut paths = entries.map(|e| e.unwrap().path()).collect::<Vec<_>>();
+        paths.sort();
+        paths
+            .into_iter()
+            .map(|path| {
+                let file = std::fs::File::open(path).unwrap();
+                serde_yaml::from_reader(file).unwrap()
+            })
+            .collect()
+    }
+
+    /// Deliver again the given consignments (e.g. the ones saved by any wallet), returning the
+    /// outcome of each acceptance
+    pub fn replay_consignments(
+        &mut self,
+        consignments: Vec<Transfer>,
+    ) -> Vec<Result<(), TestError>> {
+        let results = consignments
+            .into_iter()
+            .map(|consignment| {
+                println!("replaying consignment {}", consignment.consignment_id());
+                self.try_accept_transfer(consignment, None)
+            })
+            .collect();
+        self.sync_and_update_witnesses(None);
+        results
+    }
+
+    /// Save the consignment in the wallet directory, unless already there
+    fn save_consignment(&self, consignment: &Transfer) {
+        self.save_consignment_in("consignments", consignment);
+    }
+
+    /// Save consignments revealing the wallet outputs of the given colored PSBT (i.e. the change),
+    /// which otherwise would be known only by the stock
+    fn save_change_consignments(&self, psbt: &Psbt) {
+        let txid = psbt.txid();
+        let seals = psbt
+            .outputs()
+            .filter(|o| o.terminal_derivation().is_some())
+            .map(|o| {
+                XChain::Bitcoin(ExplicitSeal::new(
+                    self.close_method(),
+                    Outpoint::new(txid, o.index() as u32),
+                ))
+            })
+            .collect::<Vec<_>>();
+        if seals.is_empty() {
+            return;
+        }
+        for contract_id in psbt.rgb_contract_ids().unwrap() {
+            let consignment = self
+                .wallet
+                .stock()
+                .transfer(contract_id, seals.clone(), None)
+                .unwrap();
+            self.save_consignment_in("change", &consignment);
+        }
+    }
+
+    /// Save the tapret tweak of the wallet output hosting the commitment of the given TX
+    fn save_tapret_tweak(
+        &self,
+        txid: Txid,
+        terminal: Terminal,
+        tapret_commitment: TapretCommitment,
+    ) {
+        let mut tweak_path = self.wallet_dir.join("tweaks");
+        std::fs::create_dir_all(&tweak_path).unwrap();
+        tweak_path.push(txid.to_string());
+        tweak_path.set_extension("yaml");
+        let file = std::fs::File::create(tweak_path).unwrap();
+        serde_yaml::to_writer(file, &(terminal, tapret_commitment)).unwrap();
+    }
+
+    fn save_consignment_in(&self, dir: &str, consignment: &Transfer) {
+        let mut cs_path = self.wallet_dir.join(dir);
         std::fs::create_dir_all(&cs_path).unwrap();
-        let consignment_id = consignment.consignment_id();
-        cs_path.push(consignment_id.to_string());
+        cs_path.push(consignment.consignment_id().to_string());
         cs_path.set_extension("yaml");
+        if cs_path.exists() {
+            return;
+        }
         let mut file = std::fs::File::options()
             .read(true)
             .write(true)
             .create_new(true)
             .open(cs_path)
             .unwrap();
-        serde_yaml::to_writer(&mut file, &consignment).unwrap();
+        serde_yaml::to_writer(&mut file, consignment).unwrap();
+    }
 
-        let tx = self.sign_finalize_extract(&mut psbt);
+    pub fn transfer(
+        &mut self,
+        invoice: RgbInvoice,
+        sats: Option<u64>,
+        fee: Option<u64>,
+        broadcast: bool,
+        report: Option<&Report>,
+    ) -> PendingTransfer {
+        self.try_transfer(invoice, sats, fee, broadcast, report)
+            .unwrap()
+    }
+
+    /// Like [`TestWallet::transfer`], returning the error instead of panicking if the invoice
+    /// can't be paid
+    pub fn try_transfer(
+        &mut self,
+        invoice: RgbInvoice,
+        sats: Option<u64>,
+        fee: Option<u64>,
+        broadcast: bool,
+        report: Option<&Report>,
+    ) -> Result<PendingTransfer, TestError> {
+        let (consignment, mut psbt) =
+            self.try_transfer_unsigned(invoice.clone(), sats, fee, report)?;
+
+        let tx = self.try_sign_finalize_extract(&mut psbt)?;
 
         let txid = tx.txid().to_string();
-        println!("transfer txid: {txid}, consignment: {consignment_id}");
+        println!(
+            "transfer txid: {txid}, consignment: {}",
+            consignment.consignment_id()
+        );
 
         let mut tx_path = self.wallet_dir.join("transactions");
         std::fs::create_dir_all(&tx_path).unwrap();
@@ -1000,17 +2653,61 @@ impl TestWallet {
         writeln!(file, "\n---\n").unwrap();
         serde_yaml::to_writer(&mut file, &psbt).unwrap();
 
-        if broadcast {
+        let status = if broadcast {
             self.broadcast_tx(&tx);
-        }
+            TransferStatus::Broadcast
+        } else {
+            TransferStatus::Created
+        };
 
-        (consignment, tx)
+        Ok(PendingTransfer::new(invoice, consignment, tx, status))
+    }
+
+    /// Transfer paying the given fee rate (sat/vB), returning also the paid fee
+    pub fn transfer_fee_rate(
+        &mut self,
+        invoice: RgbInvoice,
+        sats: Option<u64>,
+        fee_rate: f64,
+        broadcast: bool,
+    ) -> (Transfer, Tx, u64) {
+        self.sync();
+
+        // dry run to know the TX inputs and outputs, without committing to it
+        let params = TransferParams::with(
+            Sats::from_sats(DEFAULT_FEE_ABS),
+            Sats::from_sats(sats.unwrap_or(2000)),
+        );
+        let (psbt, _psbt_meta) = self.wallet.construct_psbt(&invoice, params).unwrap();
+        let fee = self.fee_from_rate(&psbt, fee_rate);
+
+        let (consignment, tx) = self
+            .transfer(invoice, sats, Some(fee), broadcast, None)
+            .into_parts();
+        (consignment, tx, fee)
     }
 
     pub fn accept_transfer(&mut self, consignment: Transfer, report: Option<&Report>) {
+        self.try_accept_transfer(consignment, report).unwrap();
+    }
+
+    pub fn try_accept_transfer(
+        &mut self,
+        consignment: Transfer,
+        report: Option<&Report>,
+    ) -> Result<(), TestError> {
         let mut resolver = self.get_resolver();
         resolver.add_terminals(&consignment);
-        self.accept_transfer_custom_resolver(consignment, report, &resolver);
+        self.try_accept_transfer_custom_resolver(consignment, report, &resolver)
+    }
+
+    pub fn accept_transfer_offchain(&mut self, consignment: Transfer, witness_txid: Txid) {
+        let resolver = OffchainResolver {
+            witness_id: XChain::Bitcoin(witness_txid),
+            consignment: &IndexedConsignment::new(&consignment),
+            fallback: &self.get_resolver(),
+        };
+        self.accept_transfer_custom_resolver(consignment.clone(), None, &resolver);
     }
 
     pub fn accept_transfer_custom_resolver(
@@ -1019,29 +2716,108 @@ impl TestWallet {
         report: Option<&Report>,
         resolver: &impl ResolveWitness,
     ) {
-        self.sync();
+        self.try_accept_transfer_custom_resolver(consignment, report, resolver)
+            .unwrap();
+    }
+
+    pub fn try_accept_transfer_custom_resolver(
+        &mut self,
+        consignment: Transfer,
+        report: Option<&Report>,
+        resolver: &impl ResolveWitness,
+    ) -> Result<(), TestError> {
+        self.try_sync()?;
         let validate_start = Instant::now();
         let validated_consignment = consignment
+            .clone()
             .validate(&resolver, self.testnet())
-            .map_err(|(status, _)| status)
-            .unwrap();
+            .map_err(|(status, _)| status)?;
         let validate_duration = validate_start.elapsed();
         if let Some(report) = report {
             report.write_duration(validate_duration);
         }
 
         let validation_status = validated_consignment.clone().into_validation_status();
-        let validity = validation_status.validity();
-        assert_eq!(validity, Validity::Valid);
+        if validation_status.validity() != Validity::Valid {
+            return Err(validation_status.into());
+        }
         let accept_start = Instant::now();
         self.wallet
             .stock_mut()
-            .accept_transfer(validated_consignment.clone(), &resolver)
-            .unwrap();
+            .accept_transfer(validated_consignment.clone(), &resolver)?;
         let accept_duration = accept_start.elapsed();
         if let Some(report) = report {
             report.write_duration(accept_duration);
         }
+        self.save_consignment(&consignment);
+        Ok(())
+    }
+
+    /// Accept the consignment only if it pays the given invoice with the given witness TX.
+    ///
+    /// Everything is checked before importing the consignment: contract, witness, and the
+    /// beneficiary (blinded seal or witness TX vout) getting the invoiced amount in the terminal
+    /// transitions.
+    pub fn try_accept_transfer_for_invoice(
+        &mut self,
+        consignment: Transfer,
+        invoice: &RgbInvoice,
+        witness_txid: Txid,
+    ) -> Result<(), TestError> {
+        let contract_id = consignment.contract_id();
+        if let Some(invoiced_contract_id) = invoice.contract {
+            if contract_id != invoiced_contract_id {
+                return Err(TestError::Rejected(format!(
+                    "consignment for contract {contract_id} instead of {invoiced_contract_id}"
+                )));
+            }
+        }
+
+        if self.get_tx_height(&witness_txid).is_none() {
+            return Err(TestError::Rejected(format!(
+                "witness TX {witness_txid} is not mined"
+            )));
+        }
+
+        let beneficiary = invoice.beneficiary.into_inner();
+        let mut witness_vouts = vec![];
+        if let Beneficiary::WitnessVout(pay2vout) = &beneficiary {
+            let witness = self
+                .get_resolver()
+                .resolve_pub_witness(XWitnessId::Bitcoin(witness_txid))?;
+            let script_pubkey = pay2vout.address.script_pubkey();
+            witness_vouts = witness
+                .as_reduced_unsafe()
+                .outputs
+                .iter()
+                .enumerate()
+                .filter(|(_, o)| o.script_pubkey == script_pubkey)
+                .map(|(vout, _)| vout as u32)
+                .collect::<Vec<_>>();
+            if witness_vouts.is_empty() {
+                return Err(TestError::Rejected(format!(
+                    "witness TX {witness_txid} doesn't pay the invoice beneficiary"
+                )));
+            }
+        }
+
+        let paid_amt = terminal_amount(&consignment, |secret_seal, vout| match &beneficiary {
+            Beneficiary::BlindedSeal(invoice_seal) => secret_seal == *invoice_seal,
+            Beneficiary::WitnessVout(_) => vout.is_some_and(|vout| witness_vouts.contains(&vout)),
+        })
+        .ok_or_else(|| {
+            TestError::Rejected(s!("consignment assigns nothing to the invoice beneficiary"))
+        })?;
+        if let InvoiceState::Amount(invoiced_amt) = &invoice.owned_state {
+            if paid_amt < invoiced_amt.value() {
+                return Err(TestError::Rejected(format!(
+                    "received {paid_amt} instead of {}",
+                    invoiced_amt.value()
+                )));
+            }
+        }
+
+        self.try_accept_transfer(consignment, None)
     }
 
     pub fn contract_iface(
@@ -1082,6 +2858,40 @@ impl TestWallet {
             .collect()
     }
 
+    /// Fungible amount owned by the wallet, zero if the contract is unknown
+    pub fn contract_fungible_owned_amount(
+        &self,
+        contract_id: ContractId,
+        iface_type_name: &TypeName,
+    ) -> u64 {
+        if !self
+            .list_contracts()
+            .iter()
+            .any(|info| info.id == contract_id)
+        {
+            return 0;
+        }
+        self.contract_fungible_allocations(contract_id, iface_type_name, false)
+            .iter()
+            .map(|a| a.state.value())
+            .sum()
+    }
+
+    /// Fungible amount assigned to the given outpoints, whether they are owned by the wallet or not
+    pub fn contract_fungible_amount_on(
+        &self,
+        contract_id: ContractId,
+        iface_type_name: &TypeName,
+        outpoints: &[Outpoint],
+    ) -> u64 {
+        self.contract_iface(contract_id, iface_type_name)
+            .fungible(fname!("assetOwner"), Filter::NoWallet)
+            .unwrap()
+            .filter(|a| outpoints.contains(a.seal.to_outpoint().as_reduced_unsafe()))
+            .map(|a| a.state.value())
+            .sum()
+    }
+
     pub fn contract_data_allocations(
         &self,
         contract_id: ContractId,
@@ -1093,6 +2903,17 @@ impl TestWallet {
             .collect()
     }
 
+    /// Check the wallet owns the whole UDA token with the given index
+    pub fn check_uda_allocation(&self, contract_id: ContractId, token_index: u32) {
+        let allocations =
+            self.contract_data_allocations(contract_id, &AssetSchema::Uda.iface_type_name());
+        assert_eq!(allocations.len(), 1);
+        assert_eq!(
+            allocations[0].state.to_string(),
+            uda_allocation_state(token_index)
+        );
+    }
+
     pub fn history(&self, contract_id: ContractId, iface_type_name: &TypeName) -> Vec<ContractOp> {
         self.wallet
             .history(contract_id, iface_type_name.clone())
@@ -1103,6 +2924,13 @@ impl TestWallet {
         self.wallet.stock().contracts().unwrap().collect()
     }
 
+    pub fn contract_info(&self, contract_id: ContractId) -> ContractInfo {
+        self.list_contracts()
+            .into_iter()
+            .find(|info| info.id == contract_id)
+            .expect("unknown contract")
+    }
+
     pub fn utxos(&self) -> Vec<WalletUtxo> {
         self.wallet.wallet().utxos().collect()
     }
@@ -1291,13 +3119,201 @@ impl TestWallet {
         fee: Option<u64>,
         report: Option<&Report>,
     ) -> (Transfer, Tx) {
-        let (consignment, tx) = self.transfer(invoice, sats, fee, true, report);
+        let (consignment, tx) = self.transfer(invoice, sats, fee, true, report).into_parts();
         self.mine_tx(&tx.txid(), false);
-        recv_wlt.accept_transfer(consignment.clone(), report);
+        recv_wlt.accept_transfer(self.deliver_consignment(&consignment), report);
         self.sync();
         (consignment, tx)
     }
 
+    /// Pay the invoice and upload the consignment to the proxy, instead of delivering it directly
+    /// to the receiver
+    pub fn send_via_proxy(
+        &mut self,
+        proxy: &ProxyClient,
+        invoice: RgbInvoice,
+        sats: Option<u64>,
+    ) -> (Transfer, Tx) {
+        let recipient_id = proxy_recipient_id(&invoice);
+        let (consignment, tx) = self.transfer(invoice, sats, None, true, None).into_parts();
+        proxy
+            .post_consignment(&recipient_id, &consignment, tx.txid())
+            .unwrap();
+        (consignment, tx)
+    }
+
+    /// Poll the proxy for the consignment paying the invoice, as a receiver coming online does.
+    ///
+    /// Returns `None` if nothing has been uploaded yet or if the witness TX is not mined, since it
+    /// may still confirm. Otherwise the consignment is accepted and ACKed if it pays the invoice,
+    /// NACKed if not.
+    pub fn receive_via_proxy(
+        &mut self,
+        proxy: &ProxyClient,
+        invoice: &RgbInvoice,
+    ) -> Option<Result<(), TestError>> {
+        let recipient_id = proxy_recipient_id(invoice);
+        let (consignment, txid) = proxy.get_consignment(&recipient_id).unwrap()?;
+        self.sync();
+        self.get_tx_height(&txid)?;
+        let res = self.try_accept_transfer_for_invoice(consignment, invoice, txid);
+        proxy.post_ack(&recipient_id, res.is_ok()).unwrap();
+        Some(res)
+    }
+
+    /// Send BTC spending all the wallet UTXOs, regardless of the RGB allocations they hold
+    pub fn send_btc(&mut self, address: Address, sats: u64) -> Tx {
+        self.sync();
+        let input_outpoints = self.utxos().iter().map(|u| u.outpoint).collect();
+        let (mut psbt, _meta) =
+            self.construct_psbt(input_outpoints, vec![(address, Some(sats))], None);
+        let tx = self.sign_finalize_extract(&mut psbt);
+        self.broadcast_tx(&tx);
+        self.mine_tx(&tx.txid(), false);
+        tx
+    }
+
+    /// Bump the fee of an unconfirmed TX with a child spending the given output of it to the
+    /// wallet, carrying forward its RGB allocations with a blank transition
+    pub fn cpfp(&mut self, parent_outpoint: Outpoint, fee: u64) -> Tx {
+        self.sync();
+        let (mut psbt, _meta) = self.construct_psbt(vec![parent_outpoint], vec![], Some(fee));
+        let coloring_info = self.carry_forward_coloring_info(&[parent_outpoint], 0);
+        if !coloring_info.asset_info_map.is_empty() {
+            let (fascia, _asset_beneficiaries) = self.color_psbt(&mut psbt, coloring_info);
+            self.consume_fascia(fascia, psbt.txid());
+        }
+        let tx = self.sign_finalize_extract(&mut psbt);
+        println!("CPFP txid: {}", tx.txid());
+        self.broadcast_tx(&tx);
+        tx
+    }
+
+    /// Provably burn the given amount of a fungible asset, assigning it to an OP_RETURN output of
+    /// the witness TX, which can never be spent. The schemata define no burn (nor replace)
+    /// operation, so this is the only way to destroy assets that can be proven to third parties.
+    /// Returns the burn outpoint and the mined witness TX.
+    pub fn burn(&mut self, contract_id: ContractId, amount: u64) -> (Outpoint, Tx) {
+        self.sync();
+        let info = self
+            .list_contracts()
+            .into_iter()
+            .find(|info| info.id == contract_id)
+            .expect("unknown contract");
+        let iface_type_name = match AssetSchema::try_from(info.schema_id) {
+            Ok(asset_schema @ (AssetSchema::Nia | AssetSchema::Cfa)) => {
+                asset_schema.iface_type_name()
+            }
+            Ok(AssetSchema::Uda) => panic!("non-fungible assets cannot be burned"),
+            Err(schema_id) => panic!("cannot burn assets of unknown schema {schema_id}"),
+        };
+        let input_outpoints = self
+            .utxos()
+            .into_iter()
+            .map(|u| u.outpoint)
+            .filter(|outpoint| self.utxo_contracts(*outpoint).contains(&contract_id))
+            .collect::<Vec<_>>();
+        let available_amt =
+            self.contract_fungible_amount_on(contract_id, &iface_type_name, &input_outpoints);
+        let change_amt = available_amt
+            .checked_sub(amount)
+            .expect("not enough assets to burn");
+
+        // other contracts on the spent UTXOs go to the change, at vout 0. A TX can have only one
+        // standard OP_RETURN output, so with opret the burn output also hosts the commitment:
+        // the assets assigned to it stay unspendable either way
+        let (mut psbt, _meta) = self.construct_psbt(input_outpoints.clone(), vec![], None);
+        let burn_vout = psbt.outputs().count() as u32;
+        let _output = psbt.construct_output_expect(ScriptPubkey::op_return(&[]), Sats::ZERO);
+        let mut coloring_info = self.carry_forward_coloring_info(&input_outpoints, 0);
+        coloring_info
+            .asset_info_map
+            .get_mut(&contract_id)
+            .unwrap()
+            .output_map = HashMap::from([(0, change_amt), (burn_vout, amount)]);
+        let (fascia, _asset_beneficiaries) = self.color_psbt(&mut psbt, coloring_info);
+        self.consume_fascia(fascia, psbt.txid());
+
+        let tx = self.sign_finalize_extract(&mut psbt);
+        println!("burn txid: {}", tx.txid());
+        self.broadcast_tx(&tx);
+        self.mine_tx(&tx.txid(), false);
+        self.sync_and_update_witnesses(None);
+        (Outpoint::new(tx.txid(), burn_vout), tx)
+    }
+
+    /// Fungible amount assigned to the OP_RETURN outputs of the given TX, hence burned
+    pub fn burned_amount(
+        &self,
+        contract_id: ContractId,
+        iface_type_name: &TypeName,
+        tx: &Tx,
+    ) -> u64 {
+        let burn_outpoints = tx
+            .outputs
+            .iter()
+            .enumerate()
+            .filter(|(_, output)| output.script_pubkey.is_op_return())
+            .map(|(vout, _)| Outpoint::new(tx.txid(), vout as u32))
+            .collect::<Vec<_>>();
+        self.contract_fungible_amount_on(contract_id, iface_type_name, &burn_outpoints)
+    }
+
+    /// Send BTC selecting the inputs with the given policy, carrying forward the RGB allocations of
+    /// colored inputs to the change, returning also the number of blank transitions
+    pub fn send_btc_with_coin_selection(
+        &mut self,
+        address: Address,
+        sats: u64,
+        coin_selection: CoinSelection,
+    ) -> (Tx, usize) {
+        self.sync();
+        let input_outpoints = self.select_coins(sats + DEFAULT_FEE_ABS, coin_selection);
+        let (psbt, _meta) =
+            self.construct_psbt(input_outpoints.clone(), vec![(address, Some(sats))], None);
+        self._spend_carrying_forward(psbt, &input_outpoints, 1)
+    }
+
+    /// Merge UTXOs selected with the given policy into a single output, carrying forward the RGB
+    /// allocations of colored inputs, returning also the number of blank transitions
+    pub fn consolidate_utxos(&mut self, coin_selection: CoinSelection) -> (Tx, usize) {
+        self.sync();
+        let input_outpoints = match coin_selection {
+            CoinSelection::PlainOnly => self
+                .utxos()
+                .into_iter()
+                .map(|u| u.outpoint)
+                .filter(|outpoint| self.utxo_contracts(*outpoint).is_empty())
+                .collect(),
+            CoinSelection::LargestFirst | CoinSelection::PreferPlain => self
+                .utxos()
+                .into_iter()
+                .map(|u| u.outpoint)
+                .collect::<Vec<_>>(),
+        };
+        let (psbt, _meta) = self.construct_psbt(input_outpoints.clone(), vec![], None);
+        self._spend_carrying_forward(psbt, &input_outpoints, 0)
+    }
+
+    fn _spend_carrying_forward(
+        &mut self,
+        mut psbt: Psbt,
+        input_outpoints: &[Outpoint],
+        change_vout: u32,
+    ) -> (Tx, usize) {
+        let coloring_info = self.carry_forward_coloring_info(input_outpoints, change_vout);
+        let blank_transitions = coloring_info.asset_info_map.len();
+        if blank_transitions > 0 {
+            let (fascia, _asset_beneficiaries) = self.color_psbt(&mut psbt, coloring_info);
+            self.consume_fascia(fascia, psbt.txid());
+        }
+        let tx = self.sign_finalize_extract(&mut psbt);
+        self.broadcast_tx(&tx);
+        self.mine_tx(&tx.txid(), false);
+        self.sync_and_update_witnesses(None);
+        (tx, blank_transitions)
+    }
+
     pub fn check_allocations(
         &self,
         contract_id: ContractId,
@@ -1362,8 +3378,9 @@ impl TestWallet {
         assert!(matches!(operation.state, AllocatedState::Amount(amt) if amt.value() == amount));
     }
 
-    fn _construct_psbt_offchain(
+    fn _construct_psbt_offchain<D: Descriptor<XpubDerivable>>(
         &mut self,
+        input_descriptor: &D,
         input_outpoints: Vec<(Outpoint, u64, Terminal)>,
         beneficiaries: Vec<&PsbtBeneficiary>,
         tx_params: TxParams,
@@ -1373,7 +3390,7 @@ impl TestWallet {
         for (outpoint, value, terminal) in input_outpoints {
             psbt.construct_input_expect(
                 Prevout::new(outpoint, Sats::from(value)),
-                self.wallet.wallet().descriptor(),
+                input_descriptor,
                 terminal,
                 tx_params.seq_no,
             );
@@ -1465,7 +3482,24 @@ impl TestWallet {
         let beneficiaries = self._construct_beneficiaries(beneficiaries);
         let beneficiaries: Vec<&PsbtBeneficiary> = beneficiaries.iter().collect();
 
-        self._construct_psbt_offchain(input_outpoints, beneficiaries, tx_params)
+        let descriptor = self.wallet.wallet().descriptor().clone();
+        self._construct_psbt_offchain(&descriptor, input_outpoints, beneficiaries, tx_params)
+    }
+
+    /// Like [`TestWallet::construct_psbt_offchain`], spending outputs of the given descriptor
+    /// instead of the wallet ones. The change still goes to the wallet.
+    pub fn construct_psbt_offchain_for<D: Descriptor<XpubDerivable>>(
+        &mut self,
+        input_descriptor: &D,
+        input_outpoints: Vec<(Outpoint, u64, Terminal)>,
+        beneficiaries: Vec<(Address, Option<u64>)>,
+        fee: Option<u64>,
+    ) -> (Psbt, PsbtMeta) {
+        let tx_params = TxParams::with(Sats::from_sats(fee.unwrap_or(DEFAULT_FEE_ABS)));
+        let beneficiaries = self._construct_beneficiaries(beneficiaries);
+        let beneficiaries: Vec<&PsbtBeneficiary> = beneficiaries.iter().collect();
+
+        self._construct_psbt_offchain(input_descriptor, input_outpoints, beneficiaries, tx_params)
     }
 
     pub fn construct_psbt(
@@ -1484,6 +3518,48 @@ impl TestWallet {
             .unwrap()
     }
 
+    /// Estimate the vsize of the signed TX, including the RGB commitment
+    pub fn estimate_vsize(&self, psbt: &Psbt) -> u64 {
+        // version, locktime, input and output counts, segwit marker and flag
+        let mut vsize = 11;
+        let input_vsize = match self.descriptor_type() {
+            DescriptorType::Wpkh => 68,
+            DescriptorType::Tr => 58,
+        };
+        vsize += psbt.inputs().count() as u64 * input_vsize;
+        for output in psbt.outputs() {
+            if output.script.is_op_return() {
+                vsize += OPRET_OUTPUT_VSIZE;
+            } else {
+                vsize += 9 + output.script.len() as u64;
+            }
+        }
+        // tapret commitments tweak an existing output instead, not changing the TX size
+        if self.close_method() == CloseMethod::OpretFirst
+            && !psbt.outputs().any(|o| o.script.is_op_return())
+        {
+            vsize += OPRET_OUTPUT_VSIZE;
+        }
+        vsize
+    }
+
+    pub fn fee_from_rate(&self, psbt: &Psbt, fee_rate: f64) -> u64 {
+        (self.estimate_vsize(psbt) as f64 * fee_rate).ceil() as u64
+    }
+
+    pub fn construct_psbt_fee_rate(
+        &mut self,
+        input_outpoints: Vec<Outpoint>,
+        beneficiaries: Vec<(Address, Option<u64>)>,
+        fee_rate: f64,
+    ) -> (Psbt, PsbtMeta) {
+        // dry run to know the TX inputs and outputs
+        let (psbt, _meta) =
+            self.construct_psbt(input_outpoints.clone(), beneficiaries.clone(), None);
+        let fee = self.fee_from_rate(&psbt, fee_rate);
+        self.construct_psbt(input_outpoints, beneficiaries, Some(fee))
+    }
+
     pub fn psbt_add_input(&self, psbt: &mut Psbt, utxo: Outpoint) {
         for account in self.descriptor.xpubs() {
             psbt.xpubs.insert(*account.xpub(), account.origin().clone());
@@ -1497,14 +3573,115 @@ impl TestWallet {
         );
     }
 
-    pub fn color_psbt(
+    pub fn utxo_sats(&self, utxo: Outpoint) -> u64 {
+        self.wallet.wallet().utxo(utxo).unwrap().value.sats()
+    }
+
+    pub fn psbt_add_change_output(&mut self, psbt: &mut Psbt, sats: u64) -> u32 {
+        let keychain = Keychain::with(self.keychain() as u8);
+        let index = self
+            .wallet
+            .wallet_mut()
+            .next_derivation_index(keychain, true);
+        psbt.construct_change_expect(
+            self.wallet.wallet().descriptor(),
+            Terminal::new(keychain, index),
+            Sats::from_sats(sats),
+        )
+        .index() as u32
+    }
+
+    /// Coloring info moving all the allocations of the given inputs to the given vout
+    pub fn carry_forward_coloring_info(
         &self,
+        input_outpoints: &[Outpoint],
+        vout: u32,
+    ) -> ColoringInfo {
+        let mut asset_info_map = HashMap::new();
+        for info in self.list_contracts() {
+            let has_assignments = self
+                .wallet
+                .stock()
+                .contract_assignments_for(
+                    info.id,
+                    input_outpoints
+                        .iter()
+                        .map(|o| XOutpoint::from(XChain::Bitcoin(*o))),
+                )
+                .unwrap()
+                .values()
+                .any(|opout_state_map| !opout_state_map.is_empty());
+            if !has_assignments {
+                continue;
+            }
+            let asset_schema = AssetSchema::try_from(info.schema_id).unwrap_or_else(|schema_id| {
+                panic!("cannot carry forward allocations of unknown schema {schema_id}")
+            });
+            let iface_type_name = asset_schema.iface_type_name();
+            let mut output_map = HashMap::new();
+            if asset_schema != AssetSchema::Uda {
+                let amount =
+                    self.contract_fungible_amount_on(info.id, &iface_type_name, input_outpoints);
+                output_map.insert(vout, amount);
+            }
+            asset_info_map.insert(
+                info.id,
+                AssetColoringInfo {
+                    iface: iface_type_name,
+                    input_outpoints: input_outpoints.to_vec(),
+                    output_map,
+                    nonfungible_vout: Some(vout),
+                    static_blinding: None,
+                },
+            );
+        }
+        ColoringInfo {
+            asset_info_map,
+            static_blinding: None,
+            nonce: None,
+            close_method: self.close_method(),
+        }
+    }
+
+    /// Register the tapret tweak of the given wallet output, so that it's recognized when syncing
+    fn add_tapret_tweak(&mut self, terminal: Terminal, tapret_commitment: TapretCommitment) {
+        self.wallet
+            .wallet_mut()
+            .descriptor_mut(|descr| {
+                descr.with_descriptor_mut(|d| d.add_tapret_tweak(terminal, tapret_commitment))
+            })
+            .unwrap();
+    }
+
+    pub fn color_psbt(
+        &mut self,
         psbt: &mut Psbt,
         coloring_info: ColoringInfo,
     ) -> (Fascia, AssetBeneficiariesMap) {
-        let asset_beneficiaries = self.color_psbt_init(psbt, coloring_info);
+        self.color_psbt_blinded(psbt, coloring_info, &[])
+    }
+
+    /// Like [`TestWallet::color_psbt`], also paying the given blinded invoices, whose contracts
+    /// must be part of the coloring info
+    pub fn color_psbt_blinded(
+        &mut self,
+        psbt: &mut Psbt,
+        coloring_info: ColoringInfo,
+        blinded_invoices: &[RgbInvoice],
+    ) -> (Fascia, AssetBeneficiariesMap) {
+        let asset_beneficiaries = self
+            .try_color_psbt_init_blinded(psbt, coloring_info, blinded_invoices)
+            .unwrap();
         psbt.complete_construction();
         let fascia = psbt.rgb_commit().unwrap();
+        if let Some(output) = psbt.outputs().find(|o| o.is_tapret_host()) {
+            let terminal = output
+                .terminal_derivation()
+                .expect("tapret host should be a wallet output");
+            let tapret_commitment = output.tapret_commitment().unwrap();
+            self.add_tapret_tweak(terminal, tapret_commitment.clone());
+            self.save_tapret_tweak(psbt.txid(), terminal, tapret_commitment);
+        }
         (fascia, asset_beneficiaries)
     }
 
@@ -1513,7 +3690,27 @@ impl TestWallet {
         psbt: &mut Psbt,
         coloring_info: ColoringInfo,
     ) -> AssetBeneficiariesMap {
-        if !psbt.outputs().any(|o| o.script.is_op_return()) {
+        self.try_color_psbt_init(psbt, coloring_info).unwrap()
+    }
+
+    pub fn try_color_psbt_init(
+        &self,
+        psbt: &mut Psbt,
+        coloring_info: ColoringInfo,
+    ) -> Result<AssetBeneficiariesMap, TestError> {
+        self.try_color_psbt_init_blinded(psbt, coloring_info, &[])
+    }
+
+    pub fn try_color_psbt_init_blinded(
+        &self,
+        psbt: &mut Psbt,
+        coloring_info: ColoringInfo,
+        blinded_invoices: &[RgbInvoice],
+    ) -> Result<AssetBeneficiariesMap, TestError> {
+        let close_method = coloring_info.close_method;
+        if close_method == CloseMethod::OpretFirst
+            && !psbt.outputs().any(|o| o.script.is_op_return())
+        {
             let _output = psbt.construct_output_expect(ScriptPubkey::op_return(&[]), Sats::ZERO);
         }
 
@@ -1530,58 +3727,63 @@ impl TestWallet {
         let assignment_name = FieldName::from("assetOwner");
 
         for (contract_id, asset_coloring_info) in coloring_info.asset_info_map.clone() {
-            let mut asset_transition_builder = self
-                .wallet
-                .stock()
-                .transition_builder(contract_id, asset_coloring_info.iface, None::<&str>)
-                .unwrap();
+            let mut asset_transition_builder = self.wallet.stock().transition_builder(
+                contract_id,
+                asset_coloring_info.iface,
+                None::<&str>,
+            )?;
             let assignment_id = asset_transition_builder
                 .assignments_type(&assignment_name)
-                .unwrap();
+                .ok_or_else(|| ColoringError::UnknownAssignment(assignment_name.clone()))?;
 
             let mut asset_available_amt = 0;
-            for (_, opout_state_map) in self
-                .wallet
-                .stock()
-                .contract_assignments_for(
-                    contract_id,
-                    prev_outputs
-                        .iter()
-                        // only retrieve assignments for owned prevouts using coloring_info
-                        .filter(|xop| {
-                            coloring_info.asset_info_map[&contract_id]
-                                .input_outpoints
-                                .contains(xop.as_reduced_unsafe())
-                        })
-                        .copied(),
-                )
-                .unwrap()
-            {
+            let mut nonfungible_inputs = vec![];
+            for (_, opout_state_map) in self.wallet.stock().contract_assignments_for(
+                contract_id,
+                prev_outputs
+                    .iter()
+                    // only retrieve assignments for owned prevouts using coloring_info
+                    .filter(|xop| {
+                        coloring_info.asset_info_map[&contract_id]
+                            .input_outpoints
+                            .contains(xop.as_reduced_unsafe())
+                    })
+                    .copied(),
+            )? {
                 for (opout, state) in opout_state_map {
-                    if let PersistedState::Amount(amt, _, _) = &state {
-                        asset_available_amt += amt.value();
+                    match &state {
+                        PersistedState::Amount(amt, _, _) => asset_available_amt += amt.value(),
+                        _ => nonfungible_inputs.push((opout, state.clone())),
                     }
-                    asset_transition_builder =
-                        asset_transition_builder.add_input(opout, state).unwrap();
+                    asset_transition_builder = asset_transition_builder.add_input(opout, state)?;
                 }
             }
 
+            let output_count = psbt.outputs().count();
+            let revealed_seal = |vout: u32| -> Result<BuilderSeal<GraphSeal>, ColoringError> {
+                if vout as usize >= output_count {
+                    return Err(ColoringError::InvalidVout(vout));
+                }
+                let graph_seal = if let Some(blinding) = asset_coloring_info.static_blinding {
+                    GraphSeal::with_blinded_vout(close_method, vout, blinding)
+                } else {
+                    GraphSeal::new_random_vout(close_method, vout)
+                };
+                Ok(BuilderSeal::Revealed(XChain::with(
+                    Layer1::Bitcoin,
+                    graph_seal,
+                )))
+            };
+
             let mut beneficiaries = vec![];
             let mut sending_amt = 0;
-            for (vout, amount) in asset_coloring_info.output_map {
+            for (vout, amount) in &asset_coloring_info.output_map {
+                let (vout, amount) = (*vout, *amount);
                 if amount == 0 {
                     continue;
                 }
                 sending_amt += amount;
-                if vout as usize > psbt.outputs().count() {
-                    panic!("invalid vout in output_map, does not exist in the given PSBT");
-                }
-                let graph_seal = if let Some(blinding) = asset_coloring_info.static_blinding {
-                    GraphSeal::with_blinded_vout(CloseMethod::OpretFirst, vout, blinding)
-                } else {
-                    GraphSeal::new_random_vout(CloseMethod::OpretFirst, vout)
-                };
-                let seal = BuilderSeal::Revealed(XChain::with(Layer1::Bitcoin, graph_seal));
+                let seal = revealed_seal(vout)?;
                 beneficiaries.push(seal);
 
                 let blinding_factor = if let Some(blinding) = asset_coloring_info.static_blinding {
@@ -1591,38 +3793,92 @@ impl TestWallet {
                 } else {
                     BlindingFactor::random()
                 };
-                asset_transition_builder = asset_transition_builder
-                    .add_fungible_state_raw(assignment_id, seal, amount, blinding_factor)
-                    .unwrap();
+                asset_transition_builder = asset_transition_builder.add_fungible_state_raw(
+                    assignment_id,
+                    seal,
+                    amount,
+                    blinding_factor,
+                )?;
+            }
+            for invoice in blinded_invoices
+                .iter()
+                .filter(|i| i.contract == Some(contract_id))
+            {
+                let Beneficiary::BlindedSeal(secret_seal) = invoice.beneficiary.into_inner() else {
+                    return Err(ColoringError::NotBlinded.into());
+                };
+                let InvoiceState::Amount(amount) = &invoice.owned_state else {
+                    return Err(ColoringError::NoAmount.into());
+                };
+                let amount = amount.value();
+                sending_amt += amount;
+                let seal = BuilderSeal::Concealed(XChain::Bitcoin(secret_seal));
+                beneficiaries.push(seal);
+                asset_transition_builder = asset_transition_builder.add_fungible_state_raw(
+                    assignment_id,
+                    seal,
+                    amount,
+                    BlindingFactor::random(),
+                )?;
             }
             if sending_amt > asset_available_amt {
-                panic!("total amount in output_map greater than available ({asset_available_amt})");
+                return Err(ColoringError::InsufficientAmount {
+                    available: asset_available_amt,
+                    requested: sending_amt,
+                }
+                .into());
+            }
+            if !nonfungible_inputs.is_empty() {
+                let vout = asset_coloring_info
+                    .nonfungible_vout
+                    .ok_or(ColoringError::NoNonFungibleVout)?;
+                let seal = revealed_seal(vout)?;
+                beneficiaries.push(seal);
+                for (opout, state) in nonfungible_inputs {
+                    asset_transition_builder =
+                        asset_transition_builder.add_owned_state_raw(opout.ty, seal, state)?;
+                }
             }
 
             if let Some(nonce) = coloring_info.nonce {
                 asset_transition_builder = asset_transition_builder.set_nonce(nonce);
             }
 
-            let transition = asset_transition_builder.complete_transition().unwrap();
+            let transition = asset_transition_builder.complete_transition()?;
             all_transitions.insert(contract_id, transition);
             asset_beneficiaries.insert(contract_id, beneficiaries);
         }
 
-        let (opreturn_index, _) = psbt
-            .to_unsigned_tx()
-            .outputs
-            .iter()
-            .enumerate()
-            .find(|(_, o)| o.script_pubkey.is_op_return())
-            .expect("psbt should have an op_return output");
-        let (_, opreturn_output) = psbt
-            .outputs_mut()
-            .enumerate()
-            .find(|(i, _)| i == &opreturn_index)
-            .unwrap();
-        opreturn_output.set_opret_host().unwrap();
+        let host_index = match close_method {
+            CloseMethod::OpretFirst => psbt
+                .outputs()
+                .position(|o| o.script.is_op_return())
+                .ok_or(ColoringError::NoHost(close_method))?,
+            // prefer an already selected host, otherwise pick a taproot output whose derivation
+            // is known, so that the tapret tweak can be registered by the wallet
+            CloseMethod::TapretFirst => psbt
+                .outputs()
+                .position(|o| o.is_tapret_host())
+                .or_else(|| {
+                    psbt.outputs()
+                        .position(|o| o.script.is_p2tr() && o.terminal_derivation().is_some())
+                })
+                .ok_or(ColoringError::NoHost(close_method))?,
+        };
+        let host_output = psbt.outputs_mut().nth(host_index).unwrap();
+        match close_method {
+            CloseMethod::OpretFirst => {
+                host_output.set_opret_host().map_err(PsbtError::from)?;
+            }
+            CloseMethod::TapretFirst if !host_output.is_tapret_host() => {
+                host_output.set_tapret_host().map_err(PsbtError::from)?;
+            }
+            CloseMethod::TapretFirst => {}
+        }
         if let Some(blinding) = coloring_info.static_blinding {
-            opreturn_output.set_mpc_entropy(blinding).unwrap();
+            host_output
+                .set_mpc_entropy(blinding)
+                .map_err(PsbtError::from)?;
         }
 
         let tx_inputs = psbt.clone().to_unsigned_tx().inputs;
@@ -1640,14 +3896,14 @@ impl TestWallet {
                 {
                     input
                         .set_rgb_consumer(contract_id, transition.id())
-                        .unwrap();
+                        .map_err(PsbtError::from)?;
                 }
             }
-            psbt.push_rgb_transition(transition, CloseMethod::OpretFirst)
-                .unwrap();
+            psbt.push_rgb_transition(transition, close_method)
+                .map_err(PsbtError::from)?;
         }
 
-        asset_beneficiaries
+        Ok(asset_beneficiaries)
     }
 
     pub fn consume_fascia(&mut self, fascia: Fascia, witness_txid: Txid) {
@@ -1680,6 +3936,31 @@ impl TestWallet {
             .unwrap();
     }
 
+    /// Consume only the bundles of the fascia for contracts known to the wallet, for TXs built
+    /// together with a counterparty (swaps, payjoins) whose bundles will be received via
+    /// consignment
+    pub fn consume_fascia_known_contracts(&mut self, fascia: Fascia, witness_txid: Txid) {
+        let known_contracts = self
+            .list_contracts()
+            .into_iter()
+            .map(|info| info.id)
+            .collect::<HashSet<_>>();
+        let bundles = fascia
+            .bundles
+            .into_iter()
+            .filter(|(contract_id, _)| known_contracts.contains(contract_id))
+            .collect::<BTreeMap<_, _>>();
+        if bundles.is_empty() {
+            return;
+        }
+        let fascia = Fascia {
+            witness: fascia.witness,
+            anchor: fascia.anchor,
+            bundles: Confined::try_from(bundles).unwrap(),
+        };
+        self.consume_fascia(fascia, witness_txid);
+    }
+
     pub fn update_witnesses(&mut self, after_height: u32) {
         let resolver = self.get_resolver();
         self.wallet
@@ -1714,4 +3995,394 @@ impl TestWallet {
         }
         transfers
     }
+
+    /// Payer side: build the payjoin PSBT paying the given witness invoice
+    pub fn payjoin_propose(&mut self, invoice: &RgbInvoice, sats: u64) -> Payjoin {
+        self.sync();
+
+        let contract_id = invoice.contract.unwrap();
+        let iface_type_name = invoice.iface.clone().unwrap();
+        let InvoiceState::Amount(amount) = &invoice.owned_state else {
+            panic!("payjoin supports only fungible invoices");
+        };
+        let Beneficiary::WitnessVout(pay2vout) = invoice.beneficiary.into_inner() else {
+            panic!("payjoin requires a witness invoice");
+        };
+        let address = Address::new(pay2vout.address, self.network().into());
+
+        let mut input_outpoints = self
+            .contract_fungible_allocations(contract_id, &iface_type_name, false)
+            .iter()
+            .map(|a| *a.seal.to_outpoint().as_reduced_unsafe())
+            .collect::<Vec<_>>();
+        input_outpoints.sort();
+        input_outpoints.dedup();
+        let (psbt, meta) =
+            self.construct_psbt(input_outpoints.clone(), vec![(address, Some(sats))], None);
+        let receiver_vout = psbt
+            .outputs()
+            .position(|o| o.script == address.script_pubkey())
+            .unwrap() as u32;
+        let change_vout = meta
+            .change_vout
+            .expect("payer should have a change output")
+            .into_u32();
+
+        // other assets on the payer inputs go to the change too
+        let mut payer_coloring_info =
+            self.carry_forward_coloring_info(&input_outpoints, change_vout);
+        let asset_coloring_info = payer_coloring_info
+            .asset_info_map
+            .get_mut(&contract_id)
+            .unwrap();
+        let available = asset_coloring_info.output_map[&change_vout];
+        asset_coloring_info.output_map = HashMap::from([
+            (receiver_vout, amount.value()),
+            (change_vout, available - amount.value()),
+        ]);
+
+        Payjoin {
+            psbt,
+            receiver_vout,
+            payer_coloring_info,
+            receiver_beneficiaries: bmap![],
+            receiver_inputs: vec![],
+            receiver_change_vout: None,
+        }
+    }
+
+    /// Receiver side: add an input and a change output to the payjoin PSBT, carrying forward the
+    /// RGB allocations of the input to the change
+    pub fn payjoin_contribute(&mut self, payjoin: &mut Payjoin, utxo: Outpoint) {
+        self.psbt_add_input(&mut payjoin.psbt, utxo);
+        let change_sats = self.utxo_sats(utxo) - DEFAULT_FEE_ABS;
+        let change_vout = self.psbt_add_change_output(&mut payjoin.psbt, change_sats);
+        let coloring_info = self.carry_forward_coloring_info(&[utxo], change_vout);
+        if !coloring_info.asset_info_map.is_empty() {
+            payjoin.receiver_beneficiaries = self.color_psbt_init(&mut payjoin.psbt, coloring_info);
+        }
+        payjoin.receiver_inputs.push(utxo);
+        payjoin.receiver_change_vout = Some(change_vout);
+    }
+
+    /// Receiver side: check the colored payjoin PSBT pays the invoice before signing it.
+    ///
+    /// The asset amount is checked on the terminal transitions of the payer consignments, before
+    /// importing them, while for BTC an output must pay the invoice address, the PSBT must spend
+    /// the contributed inputs and the receiver outputs (found by key origin) must give them back,
+    /// minus the receiver share of the fee.
+    pub fn payjoin_check(
+        &self,
+        psbt: &Psbt,
+        invoice: &RgbInvoice,
+        consignments: &[Transfer],
+        receiver_inputs: &[Outpoint],
+    ) -> Result<(), TestError> {
+        let contract_id = invoice.contract.unwrap();
+        let InvoiceState::Amount(amount) = &invoice.owned_state else {
+            panic!("payjoin supports only fungible invoices");
+        };
+        let Beneficiary::WitnessVout(pay2vout) = invoice.beneficiary.into_inner() else {
+            panic!("payjoin requires a witness invoice");
+        };
+        let address = Address::new(pay2vout.address, self.network().into());
+
+        let Some(receiver_vout) = psbt
+            .outputs()
+            .position(|o| o.script == address.script_pubkey())
+        else {
+            return Err(TestError::Rejected(s!(
+                "no output pays the invoice address"
+            )));
+        };
+        let paid: u64 = consignments
+            .iter()
+            .filter(|c| c.contract_id() == contract_id)
+            .filter_map(|c| terminal_amount(c, |_, vout| vout == Some(receiver_vout as u32)))
+            .sum();
+        if paid < amount.value() {
+            return Err(TestError::Rejected(format!(
+                "invoice requested {} but the payjoin pays {paid}",
+                amount.value()
+            )));
+        }
+        if let Some(missing) = receiver_inputs
+            .iter()
+            .find(|u| !psbt.inputs().any(|i| i.previous_outpoint == **u))
+        {
+            return Err(TestError::Rejected(format!(
+                "the payjoin doesn't spend the contributed input {missing}"
+            )));
+        }
+        let contributed: u64 = receiver_inputs.iter().map(|u| self.utxo_sats(*u)).sum();
+        let xpubs = self.descriptor.xpubs().collect::<Vec<_>>();
+        let change: u64 = psbt
+            .outputs()
+            .filter(|o| o.index() != receiver_vout)
+            .filter(|o| {
+                o.bip32_derivation
+                    .values()
+                    .chain(o.tap_bip32_derivation.values().map(|d| &d.origin))
+                    .any(|origin| xpubs.iter().any(|x| x.origin().is_subset_of(origin)))
+            })
+            .map(|o| o.amount.sats())
+            .sum();
+        if change + DEFAULT_FEE_ABS < contributed {
+            return Err(TestError::Rejected(format!(
+                "receiver contributed {contributed} sats but gets back {change}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Payer side: color the payjoin PSBT, get it checked and signed by the receiver, sign and
+    /// broadcast it
+    pub fn payjoin_finalize(
+        &mut self,
+        payjoin: Payjoin,
+        receiver: &mut TestWallet,
+        invoice: &RgbInvoice,
+    ) -> Tx {
+        self.try_payjoin_finalize(payjoin, receiver, invoice)
+            .unwrap()
+    }
+
+    pub fn try_payjoin_finalize(
+        &mut self,
+        payjoin: Payjoin,
+        receiver: &mut TestWallet,
+        invoice: &RgbInvoice,
+    ) -> Result<Tx, TestError> {
+        let Payjoin {
+            mut psbt,
+            receiver_vout,
+            payer_coloring_info,
+            receiver_inputs,
+            ..
+        } = payjoin;
+        let (fascia, payer_beneficiaries) = self.color_psbt(&mut psbt, payer_coloring_info);
+        self.consume_fascia_known_contracts(fascia.clone(), psbt.txid());
+
+        // the receiver gets the consignments before signing, to check it's getting paid, and
+        // imports them only if the check passes
+        let consignments = self.create_consignments(
+            beneficiaries_for_vout(&payer_beneficiaries, receiver_vout),
+            psbt.txid(),
+        );
+        receiver.payjoin_check(&psbt, invoice, &consignments, &receiver_inputs)?;
+        for consignment in consignments {
+            receiver.accept_transfer_offchain(consignment, psbt.txid());
+        }
+        receiver.consume_fascia_known_contracts(fascia, psbt.txid());
+
+        receiver.sign_finalize(&mut psbt);
+        let tx = self.sign_finalize_extract(&mut psbt);
+        self.broadcast_tx(&tx);
+        Ok(tx)
+    }
+}
+
+impl SwapOffer {
+    /// Coloring info sending the offered asset to the counterparty vout and the rest of the input
+    /// allocations to the party change vout
+    pub fn coloring_info(
+        &self,
+        wlt: &TestWallet,
+        counterparty_vout: u32,
+        change_vout: u32,
+    ) -> ColoringInfo {
+        let mut asset_info_map = HashMap::new();
+        if let Some((contract_id, iface_type_name, amount)) = &self.asset {
+            let available = wlt.contract_fungible_amount_on(
+                *contract_id,
+                iface_type_name,
+                &self.input_outpoints,
+            );
+            asset_info_map.insert(
+                *contract_id,
+                AssetColoringInfo {
+                    iface: iface_type_name.clone(),
+                    input_outpoints: self.input_outpoints.clone(),
+                    output_map: HashMap::from([
+                        (counterparty_vout, *amount),
+                        (change_vout, available - amount),
+                    ]),
+                    nonfungible_vout: None,
+                    static_blinding: None,
+                },
+            );
+        }
+        ColoringInfo {
+            asset_info_map,
+            static_blinding: None,
+            nonce: None,
+            close_method: wlt.close_method(),
+        }
+    }
+}
+
+impl Swap {
+    pub const MAKER_VOUT: u32 = 0;
+    pub const TAKER_VOUT: u32 = 1;
+    pub const TAKER_CHANGE_VOUT: u32 = 2;
+    pub const MAKER_CHANGE_VOUT: u32 = 3;
+    /// BTC amount of the receiving outputs, on top of the offered BTC
+    pub const RECEIVE_SATS: u64 = 1_000;
+
+    /// Construct the swap PSBT with the inputs and outputs of both parties.
+    ///
+    /// The taker change output comes before the maker one, so that it's picked as tapret host and
+    /// the taker can register the tweak when committing.
+    pub fn construct_psbt(
+        maker: &mut TestWallet,
+        maker_offer: &SwapOffer,
+        taker: &mut TestWallet,
+        taker_offer: &SwapOffer,
+    ) -> Psbt {
+        let mut psbt = Psbt::default();
+        for utxo in &maker_offer.input_outpoints {
+            maker.psbt_add_input(&mut psbt, *utxo);
+        }
+        for utxo in &taker_offer.input_outpoints {
+            taker.psbt_add_input(&mut psbt, *utxo);
+        }
+        let change_sats = |wlt: &TestWallet, offer: &SwapOffer| {
+            let input_sats: u64 = offer
+                .input_outpoints
+                .iter()
+                .map(|u| wlt.utxo_sats(*u))
+                .sum();
+            input_sats - Self::RECEIVE_SATS - offer.btc_amt - DEFAULT_FEE_ABS
+        };
+        let maker_change_sats = change_sats(maker, maker_offer);
+        let taker_change_sats = change_sats(taker, taker_offer);
+
+        psbt.construct_output_expect(
+            maker.get_address().script_pubkey(),
+            Sats::from_sats(Self::RECEIVE_SATS + taker_offer.btc_amt),
+        );
+        psbt.construct_output_expect(
+            taker.get_address().script_pubkey(),
+            Sats::from_sats(Self::RECEIVE_SATS + maker_offer.btc_amt),
+        );
+        taker.psbt_add_change_output(&mut psbt, taker_change_sats);
+        maker.psbt_add_change_output(&mut psbt, maker_change_sats);
+        psbt
+    }
+
+    /// Color the swap PSBT with the transitions of both parties, the taker committing to them
+    pub fn color(
+        mut psbt: Psbt,
+        maker: &mut TestWallet,
+        maker_coloring_info: ColoringInfo,
+        taker: &mut TestWallet,
+        taker_coloring_info: ColoringInfo,
+    ) -> Self {
+        let maker_beneficiaries = maker.color_psbt_init(&mut psbt, maker_coloring_info);
+        let (fascia, taker_beneficiaries) = taker.color_psbt(&mut psbt, taker_coloring_info);
+        maker.consume_fascia_known_contracts(fascia.clone(), psbt.txid());
+        taker.consume_fascia_known_contracts(fascia.clone(), psbt.txid());
+        Self {
+            psbt,
+            fascia,
+            maker_beneficiaries,
+            taker_beneficiaries,
+        }
+    }
+
+    /// Construct and color a swap in which both parties honor their offers
+    pub fn new(
+        maker: &mut TestWallet,
+        maker_offer: &SwapOffer,
+        taker: &mut TestWallet,
+        taker_offer: &SwapOffer,
+    ) -> Self {
+        let psbt = Self::construct_psbt(maker, maker_offer, taker, taker_offer);
+        let maker_coloring_info =
+            maker_offer.coloring_info(maker, Self::TAKER_VOUT, Self::MAKER_CHANGE_VOUT);
+        let taker_coloring_info =
+            taker_offer.coloring_info(taker, Self::MAKER_VOUT, Self::TAKER_CHANGE_VOUT);
+        Self::color(psbt, maker, maker_coloring_info, taker, taker_coloring_info)
+    }
+
+    pub fn txid(&self) -> Txid {
+        self.psbt.txid()
+    }
+
+    /// Each party sends its consignments to the counterparty, which accepts them before the swap
+    /// TX gets broadcasted
+    pub fn exchange_consignments(
+        &self,
+        maker: &mut TestWallet,
+        maker_offer: &SwapOffer,
+        taker: &mut TestWallet,
+        taker_offer: &SwapOffer,
+    ) {
+        self.try_exchange_consignments(maker, maker_offer, taker, taker_offer)
+            .unwrap();
+    }
+
+    /// Like [`Swap::exchange_consignments`], rejecting the swap without importing anything unless
+    /// both parties get what the counterparty offered
+    pub fn try_exchange_consignments(
+        &self,
+        maker: &mut TestWallet,
+        maker_offer: &SwapOffer,
+        taker: &mut TestWallet,
+        taker_offer: &SwapOffer,
+    ) -> Result<(), TestError> {
+        let maker_consignments =
+            maker.create_consignments(self.maker_beneficiaries.clone(), self.txid());
+        let taker_consignments =
+            taker.create_consignments(self.taker_beneficiaries.clone(), self.txid());
+        if !self.check_received(&taker_consignments, taker_offer, Self::MAKER_VOUT) {
+            return Err(TestError::Rejected(s!(
+                "the maker doesn't get what the taker offered"
+            )));
+        }
+        if !self.check_received(&maker_consignments, maker_offer, Self::TAKER_VOUT) {
+            return Err(TestError::Rejected(s!(
+                "the taker doesn't get what the maker offered"
+            )));
+        }
+        for consignment in maker_consignments {
+            taker.accept_transfer_offchain(consignment, self.txid());
+        }
+        for consignment in taker_consignments {
+            maker.accept_transfer_offchain(consignment, self.txid());
+        }
+        Ok(())
+    }
+
+    /// Whether the given receiving output gets what the counterparty offered, checking the
+    /// terminal transitions of the counterparty consignments
+    pub fn check_received(
+        &self,
+        consignments: &[Transfer],
+        counterparty_offer: &SwapOffer,
+        vout: u32,
+    ) -> bool {
+        let output = self.psbt.outputs().nth(vout as usize).unwrap();
+        if output.amount < Sats::from_sats(Self::RECEIVE_SATS + counterparty_offer.btc_amt) {
+            return false;
+        }
+        let Some((contract_id, _, amount)) = &counterparty_offer.asset else {
+            return true;
+        };
+        let received: u64 = consignments
+            .iter()
+            .filter(|c| c.contract_id() == *contract_id)
+            .filter_map(|c| terminal_amount(c, |_, v| v == Some(vout)))
+            .sum();
+        received >= *amount
+    }
+
+    /// Both parties sign the swap TX, which is then broadcasted and mined
+    pub fn sign_and_broadcast(&mut self, maker: &TestWallet, taker: &TestWallet) -> Tx {
+        maker.sign_finalize(&mut self.psbt);
+        let tx = taker.sign_finalize_extract(&mut self.psbt);
+        taker.broadcast_tx(&tx);
+        taker.mine_tx(&tx.txid(), false);
+        tx
+    }
 }
@@ -1,9 +1,15 @@
+// TestError wraps the RGB error types unboxed, so that tests can match on them
+#![allow(clippy::result_large_err)]
+
 pub mod chain;
+pub mod channel;
 pub mod helpers;
+pub mod proxy;
 
 pub const TEST_DATA_DIR: &str = "test-data";
 pub const INTEGRATION_DATA_DIR: &str = "integration";
 pub const STRESS_DATA_DIR: &str = "stress";
+pub const MEDIA_FPATH: &str = "tests/fixtures/rgb_logo.jpeg";
 
 pub const ELECTRUM_1_REGTEST_URL: &str = "127.0.0.1:50001";
 pub const ELECTRUM_2_REGTEST_URL: &str = "127.0.0.1:50002";
@@ -16,6 +22,7 @@ pub const ESPLORA_MAINNET_URL: &str = "https://blockstream.info/api";
 pub const FAKE_TXID: &str = "e5a3e577309df31bd606f48049049d2e1e02b048206ba232944fcc053a176ccb:0";
 pub const UDA_FIXED_INDEX: u32 = 0;
 pub const DEFAULT_FEE_ABS: u64 = 400;
+pub const OPRET_OUTPUT_VSIZE: u64 = 43;
 
 pub const INSTANCE_1: u8 = 1;
 pub const INSTANCE_2: u8 = 2;
@@ -24,13 +31,14 @@ pub const INSTANCE_3: u8 = 3;
 pub use std::{
     cell::OnceCell,
     collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    convert::Infallible,
     env::VarError,
     ffi::OsString,
     fmt::{self, Display},
     fs::OpenOptions,
     io::Write,
     num::NonZeroU32,
-    path::{PathBuf, MAIN_SEPARATOR},
+    path::{Path, PathBuf, MAIN_SEPARATOR},
     process::{Command, Stdio},
     str::FromStr,
     sync::{Mutex, Once, OnceLock, RwLock},
@@ -39,22 +47,29 @@ pub use std::{
 
 pub use amplify::{
     bmap,
-    confinement::{Confined, U16},
+    confinement::{Confined, Error as ConfinementError, U16, U32},
     map, s, ByteArray, Wrapper,
 };
+pub use armor::{ArmorParseError, StrictArmorError};
 use bitcoin_hashes::{sha256, Hash};
 pub use bp::{
+    dbc::tapret::{TapretCommitment, TapretProof},
     seals::txout::{BlindSeal, CloseMethod, ExplicitSeal},
-    ConsensusDecode, Outpoint, Sats, ScriptPubkey, SeqNo, Tx, Txid, Vout,
+    ConsensusDecode, Outpoint, Sats, ScriptPubkey, SeqNo, Tx, Txid, Vout, Weight,
 };
 pub use bpstd::{
-    h, signers::TestnetSigner, Address, DerivationPath, DerivationSeg, DerivedAddr, Descriptor,
-    HardenedIndex, Keychain, Network, Terminal, XkeyOrigin, Xpriv, XprivAccount, Xpub, XpubAccount,
+    h,
+    opcodes::{OP_CHECKMULTISIG, OP_PUSHNUM_2},
+    signers::TestnetSigner,
+    Address, AddressNetwork, CompressedPk, DerivationPath, DerivationSeg, Derive, DeriveScripts,
+    DerivedAddr, DerivedScript, Descriptor, HardenedIndex, KeyOrigin, Keychain, LegacyKeySig,
+    LegacyPk, Network, NormalIndex, SigScript, SpkClass, TapDerivation, TaprootKeySig, Terminal,
+    Witness, WitnessScript, XOnlyPk, XkeyOrigin, Xpriv, XprivAccount, Xpub, XpubAccount,
     XpubDerivable, XpubFp,
 };
 pub use bpwallet::{
-    fs::FsTextStore, indexers::esplora::Client as EsploraClient, AnyIndexer, Indexer as BpIndexer,
-    Wallet, WalletUtxo,
+    fs::FsTextStore, indexers::esplora::Client as EsploraClient, AnyIndexer, AnyIndexerError,
+    Indexer as BpIndexer, Wallet, WalletUtxo,
 };
 pub use descriptors::Wpkh;
 pub use electrum::{Client as ElectrumClient, ElectrumApi, Param};
@@ -64,11 +79,14 @@ pub use ifaces::{
     rgb21::{EmbeddedMedia, TokenData},
     rgb25, IssuerWrapper, Rgb20, Rgb21, Rgb25,
 };
+pub use indexmap::IndexMap;
 pub use once_cell::sync::Lazy;
 pub use psbt::{
-    Beneficiary as PsbtBeneficiary, Payment, Prevout, Psbt, PsbtConstructor, PsbtMeta, PsbtVer,
+    Beneficiary as PsbtBeneficiary, KeyAlreadyPresent, MpcPsbtError, OpretKeyError, Payment,
+    Prevout, Psbt, PsbtConstructor, PsbtMeta, PsbtVer, SignError, TapretKeyError,
+    UnfinalizedInputs,
 };
-pub use psrgbt::{RgbExt, RgbInExt, RgbPsbt, TxParams};
+pub use psrgbt::{RgbExt, RgbInExt, RgbPsbt, RgbPsbtError, TxParams};
 pub use rand::RngCore;
 pub use rgb::{
     info::ContractInfo,
@@ -77,36 +95,51 @@ pub use rgb::{
     persistence::{MemContract, MemContractState, Stock},
     resolvers::AnyResolver,
     stl::ContractTerms,
-    validation::{Failure, ResolveWitness, Scripts, Validity, WitnessResolverError},
+    validation::{Failure, ResolveWitness, Scripts, Status, Validity, WitnessResolverError},
     vm::{WitnessOrd, WitnessPos, XWitnessTx},
-    BlindingFactor, DescriptorRgb, GenesisSeal, GraphSeal, Identity, OpId, RgbDescr, RgbKeychain,
-    RgbWallet, TapretKey, TransferParams, Transition, WalletProvider, XOutpoint, XWitnessId,
+    BlindingFactor, CompositionError, DescriptorRgb, GenesisSeal, GraphSeal, Identity, OpId,
+    PayError, RgbDescr, RgbKeychain, RgbWallet, TapretKey, TransferParams, Transition,
+    WalletProvider, XOutpoint, XWitnessId,
 };
 pub use rgbstd::{
     containers::{
-        BuilderSeal, ConsignmentExt, Fascia, FileContent, IndexedConsignment, Kit, Transfer,
-        ValidKit,
+        BuilderSeal, ConsignmentExt, ConsignmentParseError, Contract, Fascia, FileContent,
+        IndexedConsignment, Kit, LoadError, Transfer, ValidKit,
     },
     interface::{
-        ContractBuilder, ContractIface, DataAllocation, FilterExclude, FungibleAllocation, Iface,
-        IfaceClass, IfaceId, IfaceImpl, NamedField,
+        BuilderError, ContractBuilder, ContractIface, DataAllocation, FilterExclude,
+        FungibleAllocation, Iface, IfaceClass, IfaceId, IfaceImpl, NamedField,
+    },
+    invoice::{
+        Beneficiary, ChainNet, InvoiceParseError, InvoiceState, RgbInvoice, RgbInvoiceBuilder,
+        XChainNet,
+    },
+    persistence::{
+        fs::FsBinStore, ComposeError, MemIndex, MemStash, MemState, PersistedState, SchemaIfaces,
+        StashReadProvider, StockError,
     },
-    invoice::{Beneficiary, RgbInvoice, RgbInvoiceBuilder, XChainNet},
-    persistence::{fs::FsBinStore, PersistedState, SchemaIfaces, StashReadProvider},
     schema::SchemaId,
     stl::{
-        AssetSpec, Attachment, Details, MediaType, Name, ProofOfReserves, RicardianContract, Ticker,
+        bp_tx_stl, rgb_contract_stl, AssetSpec, Attachment, Details, MediaType, Name,
+        ProofOfReserves, RicardianContract, Ticker,
     },
     Allocation, Amount, ContractId, GlobalStateType, KnownState, Layer1, Operation,
-    OutputAssignment, OwnedFraction, Precision, Schema, TokenIndex, TxoSeal, XChain,
+    OutputAssignment, OwnedFraction, Precision, Schema, SecretSeal, TokenIndex, TxoSeal,
+    TypedAssigns, XChain,
 };
 pub use rstest::rstest;
 pub use schemata::{CollectibleFungibleAsset, NonInflatableAsset, UniqueDigitalAsset};
 pub use serial_test::serial;
-pub use strict_encoding::{fname, tn, FieldName, StrictSerialize, TypeName};
-pub use strict_types::{StrictVal, TypeSystem};
+pub use strict_encoding::{
+    fname, tn, DeserializeError, FieldName, LibName, SerializeError, StrictDeserialize,
+    StrictSerialize, TypeName,
+};
+pub use strict_types::{
+    stl::std_stl, typesys::TypeFqn, typify::Error as TypifyError, value::StrictNum, StrictVal,
+    SymbolicSys, SystemBuilder, TypeSystem,
+};
 pub use strum::IntoEnumIterator;
 pub use strum_macros::EnumIter;
 pub use time::OffsetDateTime;
 
-pub use crate::utils::{chain::*, helpers::*};
+pub use crate::utils::{chain::*, channel::*, helpers::*, proxy::*};
@@ -0,0 +1,453 @@
+use std::{
+    io::{self, BufRead, BufReader, Read},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde_json::{json, Value};
+
+use super::*;
+
+pub const PROXY_PROTOCOL_VERSION: &str = "0.2";
+
+const ERR_INVALID_REQUEST: i64 = -32600;
+const ERR_METHOD_NOT_FOUND: i64 = -32601;
+const ERR_INVALID_PARAMS: i64 = -32602;
+const ERR_CANNOT_CHANGE_UPLOADED_FILE: i64 = -101;
+const ERR_CANNOT_CHANGE_ACK: i64 = -102;
+const ERR_CONSIGNMENT_NOT_FOUND: i64 = -103;
+
+/// Consignment uploaded to the proxy for a recipient
+#[derive(Clone, Debug)]
+struct ProxyEntry {
+    consignment: Vec<u8>,
+    txid: String,
+    ack: Option<bool>,
+}
+
+/// JSON-RPC request received by the proxy, with the file uploaded along with it
+struct ProxyRequest {
+    id: Value,
+    method: String,
+    params: Value,
+    file: Option<Vec<u8>>,
+}
+
+/// In-process stand-in of an RGB proxy server, relaying consignments from senders to receivers
+/// that may be offline at transfer time.
+///
+/// It serves the JSON-RPC methods of the RGB proxy protocol (`server.info`, `consignment.post`,
+/// `consignment.get`, `ack.post`, `ack.get`) over HTTP on localhost. As for the real server,
+/// consignments are uploaded as a multipart file along with the JSON-RPC fields and downloaded
+/// base64-encoded.
+pub struct ProxyServer {
+    url: String,
+    entries: Arc<Mutex<HashMap<String, ProxyEntry>>>,
+}
+
+impl ProxyServer {
+    /// Start the server on a free localhost port, serving requests until the test ends
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let entries = Arc::new(Mutex::new(HashMap::new()));
+        let server_entries = entries.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                handle_connection(stream, &server_entries);
+            }
+        });
+        Self { url, entries }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn client(&self) -> ProxyClient {
+        ProxyClient {
+            url: self.url.clone(),
+        }
+    }
+
+    /// Whether a consignment has been uploaded for the given recipient
+    pub fn has_consignment(&self, recipient_id: &str) -> bool {
+        self.entries.lock().unwrap().contains_key(recipient_id)
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, entries: &Mutex<HashMap<String, ProxyEntry>>) {
+    let Ok((content_type, body)) = read_http_request(&stream) else {
+        return;
+    };
+    let response = match parse_request(&content_type, &body) {
+        Ok(request) => handle_request(request, entries),
+        Err(message) => rpc_error(Value::Null, ERR_INVALID_REQUEST, &message),
+    }
+    .to_string();
+    let http_response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+        response.len()
+    );
+    let _ = stream.write_all(http_response.as_bytes());
+}
+
+/// Content type and body of the HTTP request sent on the given stream
+fn read_http_request(stream: &TcpStream) -> io::Result<(String, Vec<u8>)> {
+    let mut reader = BufReader::new(stream);
+    let mut content_type = String::new();
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-type") {
+                content_type = value.trim().to_string();
+            } else if name.eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+            }
+        }
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body)?;
+    Ok((content_type, body))
+}
+
+/// Read the JSON-RPC request either from a JSON body or from the fields of a multipart form
+fn parse_request(content_type: &str, body: &[u8]) -> Result<ProxyRequest, String> {
+    if let Some(boundary) = content_type
+        .strip_prefix("multipart/form-data")
+        .and_then(|params| params.split_once("boundary="))
+        .map(|(_, boundary)| boundary.trim_matches('"'))
+    {
+        let mut fields = parse_multipart(body, boundary)?;
+        let mut text_field = |name: &str| {
+            fields
+                .remove(name)
+                .map(|value| String::from_utf8_lossy(&value).into_owned())
+        };
+        let id = text_field("id")
+            .map(|id| serde_json::from_str(&id).unwrap_or(Value::String(id)))
+            .unwrap_or_default();
+        let method = text_field("method").ok_or("missing method")?;
+        let params = match text_field("params") {
+            Some(params) => serde_json::from_str(&params).map_err(|e| e.to_string())?,
+            None => Value::Null,
+        };
+        Ok(ProxyRequest {
+            id,
+            method,
+            params,
+            file: fields.remove("file"),
+        })
+    } else {
+        let mut request: Value = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+        let method = request["method"]
+            .as_str()
+            .ok_or("missing method")?
+            .to_string();
+        Ok(ProxyRequest {
+            id: request["id"].take(),
+            method,
+            params: request["params"].take(),
+            file: None,
+        })
+    }
+}
+
+/// Fields of the multipart form body, by name
+fn parse_multipart(body: &[u8], boundary: &str) -> Result<HashMap<String, Vec<u8>>, String> {
+    let delimiter = format!("--{boundary}").into_bytes();
+    let mut fields = HashMap::new();
+    let mut rest = body;
+    loop {
+        let start = find_bytes(rest, &delimiter).ok_or("missing multipart delimiter")?;
+        rest = &rest[start + delimiter.len()..];
+        if rest.starts_with(b"--") {
+            return Ok(fields);
+        }
+        let part_len = find_bytes(rest, &delimiter).ok_or("unterminated multipart form")?;
+        let part = rest[..part_len]
+            .strip_prefix(b"\r\n")
+            .and_then(|part| part.strip_suffix(b"\r\n"))
+            .ok_or("malformed multipart part")?;
+        let headers_len = find_bytes(part, b"\r\n\r\n").ok_or("missing part headers")?;
+        let headers = String::from_utf8_lossy(&part[..headers_len]);
+        let name = headers
+            .split(';')
+            .find_map(|param| param.trim().strip_prefix("name="))
+            .ok_or("unnamed multipart part")?
+            .trim_matches('"');
+        fields.insert(name.to_string(), part[headers_len + 4..].to_vec());
+        rest = &rest[part_len..];
+    }
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn handle_request(request: ProxyRequest, entries: &Mutex<HashMap<String, ProxyEntry>>) -> Value {
+    let ProxyRequest {
+        id,
+        method,
+        params,
+        file,
+    } = request;
+    let param = |name: &str| params[name].as_str().map(str::to_string);
+
+    let mut entries = entries.lock().unwrap();
+    match method.as_str() {
+        "server.info" => rpc_result(id, json!({ "protocol_version": PROXY_PROTOCOL_VERSION })),
+        "consignment.post" => {
+            let (Some(recipient_id), Some(txid), Some(consignment)) =
+                (param("recipient_id"), param("txid"), file)
+            else {
+                return rpc_error(id, ERR_INVALID_PARAMS, "missing params");
+            };
+            match entries.get(&recipient_id) {
+                // uploading again the same consignment is allowed
+                Some(entry) if entry.consignment == consignment && entry.txid == txid => {
+                    rpc_result(id, json!(true))
+                }
+                Some(_) => rpc_error(
+                    id,
+                    ERR_CANNOT_CHANGE_UPLOADED_FILE,
+                    "cannot change uploaded file",
+                ),
+                None => {
+                    entries.insert(
+                        recipient_id,
+                        ProxyEntry {
+                            consignment,
+                            txid,
+                            ack: None,
+                        },
+                    );
+                    rpc_result(id, json!(true))
+                }
+            }
+        }
+        "consignment.get" => {
+            let Some(recipient_id) = param("recipient_id") else {
+                return rpc_error(id, ERR_INVALID_PARAMS, "missing params");
+            };
+            match entries.get(&recipient_id) {
+                Some(entry) => rpc_result(
+                    id,
+                    json!({
+                        "consignment": BASE64.encode(&entry.consignment),
+                        "txid": entry.txid,
+                    }),
+                ),
+                None => rpc_error(id, ERR_CONSIGNMENT_NOT_FOUND, "consignment not found"),
+            }
+        }
+        "ack.post" => {
+            let (Some(recipient_id), Some(ack)) = (param("recipient_id"), params["ack"].as_bool())
+            else {
+                return rpc_error(id, ERR_INVALID_PARAMS, "missing params");
+            };
+            match entries.get_mut(&recipient_id) {
+                None => rpc_error(id, ERR_CONSIGNMENT_NOT_FOUND, "consignment not found"),
+                Some(entry) if entry.ack.is_some_and(|a| a != ack) => {
+                    rpc_error(id, ERR_CANNOT_CHANGE_ACK, "cannot change ACK")
+                }
+                Some(entry) => {
+                    entry.ack = Some(ack);
+                    rpc_result(id, json!(true))
+                }
+            }
+        }
+        "ack.get" => {
+            let Some(recipient_id) = param("recipient_id") else {
+                return rpc_error(id, ERR_INVALID_PARAMS, "missing params");
+            };
+            match entries.get(&recipient_id) {
+                Some(entry) => rpc_result(id, json!(entry.ack)),
+                None => rpc_error(id, ERR_CONSIGNMENT_NOT_FOUND, "consignment not found"),
+            }
+        }
+        _ => rpc_error(id, ERR_METHOD_NOT_FOUND, "method not found"),
+    }
+}
+
+fn rpc_result(id: Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+fn rpc_error(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+/// Error talking to the proxy
+#[derive(Debug)]
+pub enum ProxyError {
+    /// The proxy can't be reached
+    Io(io::Error),
+    /// The proxy reply is not the expected JSON-RPC response
+    InvalidResponse(String),
+    /// The proxy returned a JSON-RPC error
+    Rpc { code: i64, message: String },
+}
+
+impl From<io::Error> for ProxyError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl ProxyError {
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Self::Rpc { code, .. } if *code == ERR_CONSIGNMENT_NOT_FOUND)
+    }
+}
+
+/// Client of the RGB proxy protocol, used by both senders and receivers
+#[derive(Clone, Debug)]
+pub struct ProxyClient {
+    url: String,
+}
+
+impl ProxyClient {
+    fn call(&self, method: &str, params: Value) -> Result<Value, ProxyError> {
+        let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        self.send("application/json", &body.to_string().into_bytes())
+    }
+
+    /// Call the method sending the JSON-RPC fields and the file in a multipart form, as uploads
+    /// are done in the RGB proxy protocol
+    fn call_multipart(
+        &self,
+        method: &str,
+        params: Value,
+        file: &[u8],
+    ) -> Result<Value, ProxyError> {
+        let boundary = format!("rgb-tests-{:016x}", rand::random::<u64>());
+        let mut body = vec![];
+        for (name, value) in [
+            ("jsonrpc", s!("2.0")),
+            ("id", s!("1")),
+            ("method", method.to_string()),
+            ("params", params.to_string()),
+        ] {
+            write!(
+                body,
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+            )?;
+        }
+        write!(
+            body,
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"consignment.rgb\"\r\nContent-Type: application/octet-stream\r\n\r\n"
+        )?;
+        body.extend(file);
+        write!(body, "\r\n--{boundary}--\r\n")?;
+        self.send(&format!("multipart/form-data; boundary={boundary}"), &body)
+    }
+
+    fn send(&self, content_type: &str, body: &[u8]) -> Result<Value, ProxyError> {
+        let addr = self.url.trim_start_matches("http://");
+        let mut stream = TcpStream::connect(addr)?;
+        write!(
+            stream,
+            "POST /json-rpc HTTP/1.1\r\nHost: {addr}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        )?;
+        stream.write_all(body)?;
+        let mut response = vec![];
+        stream.read_to_end(&mut response)?;
+        let body_start = find_bytes(&response, b"\r\n\r\n")
+            .ok_or_else(|| ProxyError::InvalidResponse(s!("missing HTTP body")))?
+            + 4;
+        let mut response: Value = serde_json::from_slice(&response[body_start..])
+            .map_err(|e| ProxyError::InvalidResponse(e.to_string()))?;
+        if let Some(error) = response.get("error") {
+            return Err(ProxyError::Rpc {
+                code: error["code"].as_i64().unwrap_or_default(),
+                message: error["message"].as_str().unwrap_or_default().to_string(),
+            });
+        }
+        match response.get_mut("result") {
+            Some(result) => Ok(result.take()),
+            None => Err(ProxyError::InvalidResponse(s!("missing result"))),
+        }
+    }
+
+    pub fn protocol_version(&self) -> Result<String, ProxyError> {
+        let info = self.call("server.info", json!({}))?;
+        info["protocol_version"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| ProxyError::InvalidResponse(format!("invalid server info {info}")))
+    }
+
+    /// Upload the consignment for the given recipient, as a sender does after broadcasting the
+    /// witness TX
+    pub fn post_consignment(
+        &self,
+        recipient_id: &str,
+        consignment: &Transfer,
+        txid: Txid,
+    ) -> Result<(), ProxyError> {
+        let params = json!({ "recipient_id": recipient_id, "txid": txid.to_string() });
+        let file = ConsignmentFormat::Binary.serialize(consignment);
+        self.call_multipart("consignment.post", params, &file)
+            .map(|_| ())
+    }
+
+    /// Download the consignment for the given recipient with its witness TX ID, `None` if it
+    /// hasn't been uploaded
+    pub fn get_consignment(
+        &self,
+        recipient_id: &str,
+    ) -> Result<Option<(Transfer, Txid)>, ProxyError> {
+        let result = match self.call("consignment.get", json!({ "recipient_id": recipient_id })) {
+            Ok(result) => result,
+            Err(e) if e.is_not_found() => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let invalid = |e: String| ProxyError::InvalidResponse(e);
+        let bytes = BASE64
+            .decode(result["consignment"].as_str().unwrap_or_default())
+            .map_err(|e| invalid(e.to_string()))?;
+        let consignment = ConsignmentFormat::Binary
+            .deserialize(&bytes)
+            .map_err(|e| invalid(e.to_string()))?;
+        let txid = Txid::from_str(result["txid"].as_str().unwrap_or_default())
+            .map_err(|e| invalid(e.to_string()))?;
+        Ok(Some((consignment, txid)))
+    }
+
+    pub fn post_ack(&self, recipient_id: &str, ack: bool) -> Result<(), ProxyError> {
+        let params = json!({ "recipient_id": recipient_id, "ack": ack });
+        self.call("ack.post", params).map(|_| ())
+    }
+
+    /// ACK (`Some(true)`) or NACK (`Some(false)`) of the receiver, `None` if it hasn't replied yet
+    pub fn get_ack(&self, recipient_id: &str) -> Result<Option<bool>, ProxyError> {
+        let ack = self.call("ack.get", json!({ "recipient_id": recipient_id }))?;
+        match ack {
+            Value::Null => Ok(None),
+            Value::Bool(ack) => Ok(Some(ack)),
+            ack => Err(ProxyError::InvalidResponse(format!("invalid ACK {ack}"))),
+        }
+    }
+}
+
+/// Recipient ID of the given invoice, used as key on the proxy
+pub fn proxy_recipient_id(invoice: &RgbInvoice) -> String {
+    invoice.beneficiary.to_string()
+}